@@ -1,4 +1,9 @@
 global start
+global stack_start
+global stack_end
+global p4_table
+global p3_table
+global p2_table
 extern long_mode_start
 
 section .text
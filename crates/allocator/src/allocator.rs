@@ -1,153 +1,448 @@
 use core::{
-    alloc::{GlobalAlloc, Layout},
-    mem::size_of,
+    alloc::Layout,
+    cell::Cell,
+    mem::{align_of, size_of},
+    ptr::{copy_nonoverlapping, null_mut, NonNull},
 };
 
-use crate::LinkedAllocatorNode;
+#[cfg(not(feature = "debug"))]
+use core::alloc::GlobalAlloc;
+
+#[cfg(feature = "track")]
+use crate::TrackHandler;
+use crate::{linked_list::MIN_NODE_SIZE, LinkedAllocatorNode};
+
+// Called with the first address past the heap and the minimal amount of bytes the heap has to grow
+// by, returns how many bytes were actually made available at that address (0 if none)
+pub type GrowHandler = fn(heap_end: usize, min_size: usize) -> usize;
 
 pub struct LinkedListAllocator {
     pub nodes: Option<*mut LinkedAllocatorNode>,
+    // Explicit list of free nodes, allocation only has to look at these
+    pub(crate) free_list: Cell<Option<NonNull<LinkedAllocatorNode>>>,
+    grow_handler: Option<GrowHandler>,
+    #[cfg(feature = "track")]
+    pub(crate) track_handler: Option<TrackHandler>,
 }
 
 unsafe impl Send for LinkedListAllocator {}
 
-unsafe impl<'a> GlobalAlloc for LinkedListAllocator {
+#[cfg(not(feature = "debug"))]
+unsafe impl GlobalAlloc for LinkedListAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.reallocate(ptr, layout, new_size)
+    }
+}
+
+impl LinkedListAllocator {
+    pub(crate) unsafe fn allocate(&self, layout: Layout) -> *mut u8 {
         if self.nodes.is_none() {
             panic!("LinkedListAllocator has not been initialized yet");
         }
 
-        let start_node = &mut *(self.nodes_mut().unwrap());
+        loop {
+            if let Some(ptr) = self.alloc_first_fit(layout) {
+                #[cfg(feature = "track")]
+                self.track(ptr);
+                return ptr;
+            }
+
+            // Out of memory, let the caller decide what to do
+            if !self.grow(layout) {
+                return null_mut();
+            }
+        }
+    }
+
+    pub(crate) unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        if self.nodes.is_none() {
+            panic!("LinkedListAllocator has not been initialized yet");
+        }
+
+        // Allocations start right behind their node, the boundary tags make merging with both
+        // neighbours independent of the heap size
+        let node = &mut *(ptr.sub(size_of::<LinkedAllocatorNode>()) as *mut LinkedAllocatorNode);
+        if node.free {
+            panic!("Could not deallocate 0x{:x} {:?}", ptr as usize, layout);
+        }
+
+        // Merge with next allocation
+        if let Some(next) = node.physical_next() {
+            if next.free {
+                self.remove_free(next);
+                node.size += size_of::<LinkedAllocatorNode>() + next.size;
+                node.next = next.next;
+            }
+        }
+
+        // Merge with previous allocation, it is already in the free list
+        if let Some(prev) = node.prev_if_free() {
+            prev.size += size_of::<LinkedAllocatorNode>() + node.size;
+            prev.next = node.next;
+            prev.update_tags();
+            return;
+        }
+
+        self.insert_free(node);
+    }
+
+    pub(crate) unsafe fn reallocate(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        if self.nodes.is_none() {
+            panic!("LinkedListAllocator has not been initialized yet");
+        }
 
-        for node in start_node.as_iter() {
-            if !node.free {
+        let node = &mut *(ptr.sub(size_of::<LinkedAllocatorNode>()) as *mut LinkedAllocatorNode);
+        if node.free {
+            panic!("Could not reallocate 0x{:x} {:?}", ptr as usize, layout);
+        }
+        let size = node_size(new_size);
+
+        // Absorb the next node if it is free and directly behind this one
+        if size > node.size {
+            if let Some(next) = node.physical_next() {
+                if next.free && node.size + size_of::<LinkedAllocatorNode>() + next.size >= size {
+                    self.remove_free(next);
+                    node.size += size_of::<LinkedAllocatorNode>() + next.size;
+                    node.next = next.next;
+                    node.update_tags();
+                }
+            }
+        }
+
+        if size <= node.size {
+            self.split_tail(node, size);
+            return ptr;
+        }
+
+        // Can't resize in place, move the allocation
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.allocate(new_layout);
+        if !new_ptr.is_null() {
+            copy_nonoverlapping(ptr, new_ptr, layout.size());
+            self.deallocate(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+impl LinkedListAllocator {
+    pub const fn new() -> LinkedListAllocator {
+        LinkedListAllocator {
+            nodes: None,
+            free_list: Cell::new(None),
+            grow_handler: None,
+            #[cfg(feature = "track")]
+            track_handler: None,
+        }
+    }
+
+    pub fn init(&mut self, node: &'static mut LinkedAllocatorNode) {
+        if self.nodes.is_some() {
+            panic!("LinkedListAllocator is already initialized");
+        }
+        node.prev_free = false;
+        self.nodes = Some(node as *mut _);
+        if node.free {
+            unsafe { self.insert_free(node) };
+        }
+    }
+
+    pub unsafe fn nodes_mut(&self) -> Option<*mut LinkedAllocatorNode> {
+        self.nodes.map(|n| n as *mut _)
+    }
+
+    pub fn set_grow_handler(&mut self, handler: GrowHandler) {
+        self.grow_handler = Some(handler);
+    }
+
+    // Returns the first address after the last node, i.e. the end of the highest region
+    pub fn heap_end(&self) -> Option<usize> {
+        let start_node = unsafe { &*(self.nodes?) };
+        start_node
+            .as_iter()
+            .last()
+            .map(|node| node.end_address() + 1)
+    }
+
+    /// Appends `size` bytes that start at `heap_end()` to the heap. Merges them into the last node if
+    /// it is free, otherwise creates a new free node. Space too small for a node is dropped.
+    ///
+    /// # Safety
+    /// `heap_end()..heap_end() + size` has to be valid, unused memory
+    pub unsafe fn extend(&self, size: usize) {
+        let Some(last_node) = self.nodes.and_then(|n| (*n).as_iter().last()) else {
+            panic!("LinkedListAllocator has not been initialized yet");
+        };
+
+        if last_node.free {
+            last_node.size += size;
+            last_node.update_tags();
+            return;
+        }
+
+        if size < size_of::<LinkedAllocatorNode>() + MIN_NODE_SIZE {
+            return;
+        }
+
+        let new_node = &mut *((last_node.end_address() + 1) as *mut LinkedAllocatorNode);
+        *new_node = LinkedAllocatorNode::new(size);
+        last_node.next = Some(NonNull::from(&mut *new_node));
+        self.insert_free(new_node);
+    }
+
+    /// Links the memory at `start..start + size` into the heap as a free node. The region doesn't
+    /// have to be contiguous with the rest of the heap, nodes are kept sorted by address and a
+    /// region that directly borders a free node is merged into it. Initializes the allocator if it
+    /// has no nodes yet.
+    ///
+    /// # Safety
+    /// `start..start + size` has to be valid, unused memory that lives as long as the allocator
+    pub unsafe fn add_region(&mut self, start: usize, size: usize) {
+        let aligned_start = start.next_multiple_of(align_of::<LinkedAllocatorNode>());
+        let size = size.saturating_sub(aligned_start - start);
+        let size = size - size % align_of::<LinkedAllocatorNode>();
+
+        // Space too small for a node is dropped, same as in `extend`
+        if size < size_of::<LinkedAllocatorNode>() + MIN_NODE_SIZE {
+            return;
+        }
+
+        let region = &mut *(aligned_start as *mut LinkedAllocatorNode);
+        let region_end = aligned_start + size;
+
+        let Some(first) = self.nodes else {
+            *region = LinkedAllocatorNode::new(size);
+            self.nodes = Some(region);
+            self.insert_free(region);
+            return;
+        };
+
+        // Last node in front of the region
+        let prev = (*first)
+            .as_iter()
+            .take_while(|node| (*node as *const LinkedAllocatorNode as usize) < aligned_start)
+            .last();
+        let next = match prev {
+            Some(ref prev) => prev.next,
+            None => NonNull::new(first),
+        };
+
+        if let Some(ref prev) = prev {
+            if prev.end_address() >= aligned_start {
+                panic!("Region 0x{:x} overlaps with the heap", start);
+            }
+        }
+        if let Some(next) = next {
+            if (next.as_ptr() as usize) < region_end {
+                panic!("Region 0x{:x} overlaps with the heap", start);
+            }
+        }
+
+        let node = match prev {
+            Some(prev) if prev.free && prev.end_address() + 1 == aligned_start => {
+                prev.size += size;
+                prev.update_tags();
+                prev
+            }
+            prev => {
+                *region = LinkedAllocatorNode::new(size);
+                region.next = next;
+                match prev {
+                    Some(prev) => prev.next = Some(NonNull::from(&mut *region)),
+                    None => self.nodes = Some(region),
+                }
+                self.insert_free(region);
+                region
+            }
+        };
+
+        // Merge with the next node if the region fills the gap in front of it
+        if let Some(next) = node.physical_next() {
+            if next.free {
+                self.remove_free(next);
+                node.size += size_of::<LinkedAllocatorNode>() + next.size;
+                node.next = next.next;
+                node.update_tags();
+            }
+        }
+    }
+
+    unsafe fn alloc_first_fit(&self, layout: Layout) -> Option<*mut u8> {
+        let size = node_size(layout.size());
+
+        let mut current = self.free_list.get();
+        while let Some(mut node) = current {
+            let node = node.as_mut();
+            current = node.free_next;
+
+            if node.size < size {
                 continue;
             }
 
             // Try to use the node as is
-            if node.size == layout.size()
-                && node.start_address() / layout.align() * layout.align() == node.start_address()
-            {
-                node.free = false;
-                return node.start_address() as *mut u8;
+            if node.size == size && node.start_address() % layout.align() == 0 {
+                self.mark_used(node);
+                return Some(node.start_address() as *mut u8);
             }
 
             // Find where to put the allocation
-            let mut aligned_addr = (node.end_address() + 1 - layout.size()) / layout.align()
-                * layout.align()
+            let node_end = node.end_address() + 1;
+            let mut aligned_addr = (node_end - size) / layout.align() * layout.align()
                 - size_of::<LinkedAllocatorNode>();
-            let mut empty_node = None;
-
-            // section isn't at the end, add padding that is at least LinkedAllocatorNode big
-            if aligned_addr
-                != node.end_address() + 1 - layout.size() - size_of::<LinkedAllocatorNode>()
-            {
-                aligned_addr =
-                    (node.end_address() + 1 - layout.size() - size_of::<LinkedAllocatorNode>())
-                        / layout.align()
-                        * layout.align()
-                        - size_of::<LinkedAllocatorNode>();
-
-                empty_node = Some(
-                    (aligned_addr + layout.size() + size_of::<LinkedAllocatorNode>())
-                        as *mut LinkedAllocatorNode,
-                );
-                let empty_node = &mut *empty_node.unwrap();
-                empty_node.free = true;
-                empty_node.size = (node.end_address() + 1)
-                    - (aligned_addr + layout.size() + 2 * size_of::<LinkedAllocatorNode>());
-
-                assert_eq!(empty_node.end_address(), node.end_address());
-                empty_node.next = node.next;
+            let mut empty_node_addr = None;
+
+            // section isn't at the end, add padding that is big enough for a free node
+            if aligned_addr != node_end - size - size_of::<LinkedAllocatorNode>() {
+                aligned_addr = (node_end - size - size_of::<LinkedAllocatorNode>() - MIN_NODE_SIZE)
+                    / layout.align()
+                    * layout.align()
+                    - size_of::<LinkedAllocatorNode>();
+                empty_node_addr = Some(aligned_addr + size + size_of::<LinkedAllocatorNode>());
             }
 
-            // If there is space for our allocation and its node
-            if aligned_addr < node.start_address() {
+            // If there is space for our allocation and its node, the free node has to keep room for
+            // its footer. Nothing is written before this check, the node might be too small.
+            if aligned_addr < node.start_address() + MIN_NODE_SIZE {
+                // The node can still be used directly if it is aligned
+                if node.start_address() % layout.align() == 0 {
+                    self.mark_used(node);
+                    self.split_tail(node, size);
+                    return Some(node.start_address() as *mut u8);
+                }
                 continue;
             }
 
-            node.size = 0;
-
-            let new_node = &mut *((aligned_addr) as *mut LinkedAllocatorNode);
-            new_node.size = layout.size();
+            let new_node = &mut *(aligned_addr as *mut LinkedAllocatorNode);
+            new_node.size = size;
             new_node.free = false;
-            if empty_node.is_some() {
-                new_node.next = empty_node;
-            } else {
-                new_node.next = node.next;
-            }
+            new_node.prev_free = true;
+            new_node.next = node.next;
 
-            // TODO: handle this, possibly a rush condition, fuck locks but im too lazy to write
-            // actual atomic allocator xd
-            if node.size != 0 {
-                // println!("! Weird stuff happening not good, mod.rs:54");
+            if let Some(addr) = empty_node_addr {
+                let empty_node = &mut *(addr as *mut LinkedAllocatorNode);
+                empty_node.size = node_end - empty_node.start_address();
+                empty_node.prev_free = false;
+                empty_node.next = node.next;
+                self.insert_free_after(empty_node, node);
+                new_node.next = Some(NonNull::from(&mut *empty_node));
             }
 
             // Successful alloc
-            node.next = Some(new_node);
+            node.next = Some(NonNull::from(&mut *new_node));
             node.size = aligned_addr - node.start_address();
+            node.update_tags();
+            new_node.update_tags();
 
-            return (aligned_addr + size_of::<LinkedAllocatorNode>()) as *mut u8;
+            return Some(new_node.start_address() as *mut u8);
         }
 
-        panic!("No section in heap matching {:?}", layout)
+        None
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        if self.nodes.is_none() {
-            panic!("LinkedListAllocator has not been initialized yet");
+    // Shrinks the used node to (about) `size` bytes, the rest is split off into a free node if there
+    // is enough space for one and merged with the next node if that one is free as well
+    unsafe fn split_tail(&self, node: &mut LinkedAllocatorNode, size: usize) {
+        let tail_addr =
+            (node.start_address() + size).next_multiple_of(align_of::<LinkedAllocatorNode>());
+        if tail_addr + size_of::<LinkedAllocatorNode>() + MIN_NODE_SIZE > node.end_address() + 1 {
+            return;
         }
 
-        // Min alloc size is size of LinkedListAllocator, so when deallocating, the Node tag can be inserted
-        let start_node = &mut *(self.nodes_mut().unwrap());
-
-        let mut last_node: Option<&mut LinkedAllocatorNode> = None;
-        let mut iter = start_node.as_iter();
-        while let Some(node) = iter.next() {
-            if ptr as usize == node.start_address() {
-                let mut next_node = iter.next();
-                node.free = true;
+        let tail = &mut *(tail_addr as *mut LinkedAllocatorNode);
+        tail.size = node.end_address() + 1 - tail.start_address();
+        tail.prev_free = false;
+        tail.next = node.next;
 
-                // Merge with next allocation
-                if let Some(ref nnode) = next_node {
-                    if nnode.free && node.end_address() + 1 == (*nnode) as *const _ as usize {
-                        node.next = nnode.next;
-                        node.size += nnode.size + size_of::<LinkedAllocatorNode>();
-                        next_node = iter.next();
-                    }
-                }
+        if let Some(next) = tail.physical_next() {
+            if next.free {
+                self.remove_free(next);
+                tail.size += size_of::<LinkedAllocatorNode>() + next.size;
+                tail.next = next.next;
+            }
+        }
 
-                // Merge with previous allocation
-                if let Some(last_node) = last_node {
-                    if last_node.free {
-                        last_node.size += node.size + size_of::<LinkedAllocatorNode>();
-                        last_node.next = next_node.map(|n| n as *mut LinkedAllocatorNode);
-                    }
-                }
+        node.size = tail_addr - node.start_address();
+        node.next = Some(NonNull::from(&mut *tail));
+        self.insert_free(tail);
+    }
 
-                return;
-            }
+    unsafe fn insert_free(&self, node: &mut LinkedAllocatorNode) {
+        node.free = true;
+        node.free_prev = None;
+        node.free_next = self.free_list.get();
+        if let Some(mut head) = node.free_next {
+            head.as_mut().free_prev = Some(NonNull::from(&mut *node));
+        }
+        self.free_list.set(Some(NonNull::from(&mut *node)));
+        node.update_tags();
+    }
 
-            last_node = Some(node);
+    // Puts the padding split off `node` right behind it in the free list, so first fit keeps trying
+    // the lower part of the node before the padding
+    unsafe fn insert_free_after(
+        &self,
+        node: &mut LinkedAllocatorNode,
+        previous: &mut LinkedAllocatorNode,
+    ) {
+        node.free = true;
+        node.free_prev = Some(NonNull::from(&mut *previous));
+        node.free_next = previous.free_next;
+        if let Some(mut next) = node.free_next {
+            next.as_mut().free_prev = Some(NonNull::from(&mut *node));
         }
+        previous.free_next = Some(NonNull::from(&mut *node));
+        node.update_tags();
+    }
 
-        panic!("Could not deallocate 0x{:x} {:?}", ptr as usize, layout);
+    unsafe fn remove_free(&self, node: &mut LinkedAllocatorNode) {
+        match node.free_prev {
+            Some(mut prev) => prev.as_mut().free_next = node.free_next,
+            None => self.free_list.set(node.free_next),
+        }
+        if let Some(mut next) = node.free_next {
+            next.as_mut().free_prev = node.free_prev;
+        }
     }
-}
 
-impl LinkedListAllocator {
-    pub const fn new() -> LinkedListAllocator {
-        LinkedListAllocator { nodes: None }
+    unsafe fn mark_used(&self, node: &mut LinkedAllocatorNode) {
+        self.remove_free(node);
+        node.free = false;
+        node.update_tags();
     }
 
-    pub fn init(&mut self, node: &'static mut LinkedAllocatorNode) {
-        if self.nodes.is_some() {
-            panic!("LinkedListAllocator is already initialized");
+    // Asks the grow handler for enough space to fit `layout` at the end of the heap
+    unsafe fn grow(&self, layout: Layout) -> bool {
+        let (Some(handler), Some(heap_end)) = (self.grow_handler, self.heap_end()) else {
+            return false;
+        };
+
+        let min_size = layout.size() + layout.align() + 2 * size_of::<LinkedAllocatorNode>();
+        let size = handler(heap_end, min_size);
+        if size == 0 {
+            return false;
         }
-        self.nodes = Some(node as *mut _);
-    }
 
-    pub unsafe fn nodes_mut(&self) -> Option<*mut LinkedAllocatorNode> {
-        self.nodes.map(|n| n as *mut _)
+        self.extend(size);
+        true
     }
 }
+
+// Node sizes are kept at multiples of the node alignment, so the node behind an allocation is
+// aligned as well, and large enough to hold a footer once they are freed
+fn node_size(size: usize) -> usize {
+    size.max(MIN_NODE_SIZE)
+        .next_multiple_of(align_of::<LinkedAllocatorNode>())
+}
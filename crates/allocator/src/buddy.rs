@@ -0,0 +1,211 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::Cell,
+    mem::size_of,
+    ptr::{null_mut, NonNull},
+};
+
+// Every block is MIN_BLOCK_SIZE << order bytes big and aligned to its own size, so the buddy of a
+// block is always found by flipping the bit of its size in the address
+pub const MIN_BLOCK_SIZE: usize = 32;
+pub const ORDERS: usize = 32;
+
+// Lives at the start of every free block, so it has to fit the smallest one
+struct FreeBlock {
+    next: Option<NonNull<FreeBlock>>,
+    prev: Option<NonNull<FreeBlock>>,
+    order: usize,
+}
+
+const _: () = assert!(size_of::<FreeBlock>() <= MIN_BLOCK_SIZE);
+
+// The start of the region holds a bitmap with one bit per MIN_BLOCK_SIZE bytes, which is set when
+// a free block starts there. It is what makes checking whether a buddy is free O(1) without
+// touching memory that might be allocated.
+pub struct BuddyAllocator {
+    free_lists: [Cell<Option<NonNull<FreeBlock>>>; ORDERS],
+    bitmap: *mut u8,
+    start: usize,
+    end: usize,
+}
+
+unsafe impl Send for BuddyAllocator {}
+
+unsafe impl GlobalAlloc for BuddyAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if self.bitmap.is_null() {
+            panic!("BuddyAllocator has not been initialized yet");
+        }
+
+        let Some(order) = order_for(layout) else {
+            return null_mut();
+        };
+        let Some(mut current) = (order..ORDERS).find(|&o| self.free_lists[o].get().is_some())
+        else {
+            return null_mut();
+        };
+
+        let block = self.free_lists[current].get().unwrap().as_ptr() as usize;
+        self.remove(block, current);
+
+        // Split until the block has the requested size, the upper halves stay free
+        while current > order {
+            current -= 1;
+            self.push(block + block_size(current), current);
+        }
+
+        block as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if self.bitmap.is_null() {
+            panic!("BuddyAllocator has not been initialized yet");
+        }
+
+        let mut block = ptr as usize;
+        let mut order = order_for(layout).unwrap();
+        assert!(
+            block >= self.start && block + block_size(order) <= self.end,
+            "Could not deallocate 0x{:x} {:?}",
+            block,
+            layout
+        );
+
+        while order + 1 < ORDERS {
+            let buddy = block ^ block_size(order);
+            if buddy < self.start
+                || buddy + block_size(order) > self.end
+                || !self.is_free(buddy)
+                || (*(buddy as *const FreeBlock)).order != order
+            {
+                break;
+            }
+
+            self.remove(buddy, order);
+            block = block.min(buddy);
+            order += 1;
+        }
+
+        self.push(block, order);
+    }
+}
+
+impl BuddyAllocator {
+    pub const fn new() -> BuddyAllocator {
+        BuddyAllocator {
+            free_lists: [const { Cell::new(None) }; ORDERS],
+            bitmap: null_mut(),
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// Hands `start..start + size` over to the allocator, the bitmap is placed at the beginning of
+    /// the region and the rest is split into the biggest naturally aligned blocks possible
+    ///
+    /// # Safety
+    /// The region has to be valid memory that isn't used by anything else
+    pub unsafe fn init(&mut self, start: usize, size: usize) {
+        if !self.bitmap.is_null() {
+            panic!("BuddyAllocator is already initialized");
+        }
+
+        let bitmap_size = (size / MIN_BLOCK_SIZE).div_ceil(8);
+        self.bitmap = start as *mut u8;
+        self.bitmap.write_bytes(0, bitmap_size);
+
+        self.start = (start + bitmap_size).next_multiple_of(MIN_BLOCK_SIZE);
+        self.end = (start + size) / MIN_BLOCK_SIZE * MIN_BLOCK_SIZE;
+
+        let mut block = self.start;
+        while block + MIN_BLOCK_SIZE <= self.end {
+            let order = (0..ORDERS)
+                .rev()
+                .find(|&o| block.is_multiple_of(block_size(o)) && block + block_size(o) <= self.end)
+                .unwrap();
+            self.push(block, order);
+            block += block_size(order);
+        }
+    }
+
+    // Returns how many free blocks of `order` there are
+    pub fn free_blocks(&self, order: usize) -> usize {
+        self.free_list(order).count()
+    }
+
+    // Addresses of the free blocks of `order`, in the order they get handed out
+    pub fn free_list(&self, order: usize) -> impl Iterator<Item = usize> + '_ {
+        let mut current = self.free_lists[order].get();
+        core::iter::from_fn(move || {
+            let block = current?;
+            current = unsafe { block.as_ref().next };
+            Some(block.as_ptr() as usize)
+        })
+    }
+
+    unsafe fn push(&self, block: usize, order: usize) {
+        let head = self.free_lists[order].get();
+        let free_block = NonNull::new_unchecked(block as *mut FreeBlock);
+        free_block.write(FreeBlock {
+            next: head,
+            prev: None,
+            order,
+        });
+        if let Some(mut head) = head {
+            head.as_mut().prev = Some(free_block);
+        }
+
+        self.free_lists[order].set(Some(free_block));
+        self.set_free(block, true);
+    }
+
+    unsafe fn remove(&self, block: usize, order: usize) {
+        let free_block = &mut *(block as *mut FreeBlock);
+        match free_block.prev {
+            Some(mut prev) => prev.as_mut().next = free_block.next,
+            None => self.free_lists[order].set(free_block.next),
+        }
+        if let Some(mut next) = free_block.next {
+            next.as_mut().prev = free_block.prev;
+        }
+
+        self.set_free(block, false);
+    }
+
+    unsafe fn is_free(&self, block: usize) -> bool {
+        let bit = (block - self.start) / MIN_BLOCK_SIZE;
+        *self.bitmap.add(bit / 8) & (1 << (bit % 8)) != 0
+    }
+
+    unsafe fn set_free(&self, block: usize, free: bool) {
+        let bit = (block - self.start) / MIN_BLOCK_SIZE;
+        let byte = &mut *self.bitmap.add(bit / 8);
+        if free {
+            *byte |= 1 << (bit % 8);
+        } else {
+            *byte &= !(1 << (bit % 8));
+        }
+    }
+}
+
+impl Default for BuddyAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub const fn block_size(order: usize) -> usize {
+    MIN_BLOCK_SIZE << order
+}
+
+// Smallest order whose blocks fit the layout, alignment is covered by blocks being aligned to
+// their size
+pub fn order_for(layout: Layout) -> Option<usize> {
+    let size = layout
+        .size()
+        .max(layout.align())
+        .max(size_of::<FreeBlock>())
+        .checked_next_power_of_two()?;
+    let order = (size / MIN_BLOCK_SIZE).trailing_zeros() as usize;
+    (order < ORDERS).then_some(order)
+}
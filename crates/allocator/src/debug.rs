@@ -0,0 +1,187 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt,
+    mem::size_of,
+};
+
+use crate::LinkedListAllocator;
+
+pub const CANARY: u8 = 0xca;
+pub const POISON: u8 = 0xdd;
+pub const CANARY_SIZE: usize = 16;
+
+// Every allocation is wrapped like this:
+// [canary padding for alignment][DebugHeader][CANARY_SIZE canary] data [CANARY_SIZE canary]
+// The header always sits at the same offset before the data, so it can be found without knowing
+// the alignment the allocation was made with.
+#[derive(Clone, Copy)]
+struct DebugHeader {
+    size: usize,
+    align: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapError {
+    DoubleFree {
+        ptr: usize,
+    },
+    InvalidPointer {
+        ptr: usize,
+    },
+    LayoutMismatch {
+        ptr: usize,
+        allocated: Layout,
+        freed: Layout,
+    },
+    CorruptedCanary {
+        ptr: usize,
+        address: usize,
+    },
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeapError::DoubleFree { ptr } => write!(f, "double free of 0x{:x}", ptr),
+            HeapError::InvalidPointer { ptr } => write!(f, "0x{:x} was never allocated", ptr),
+            HeapError::LayoutMismatch {
+                ptr,
+                allocated,
+                freed,
+            } => write!(
+                f,
+                "0x{:x} was allocated with {:?} but freed with {:?}",
+                ptr, allocated, freed
+            ),
+            HeapError::CorruptedCanary { ptr, address } => write!(
+                f,
+                "canary of 0x{:x} was overwritten at 0x{:x}",
+                ptr, address
+            ),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for LinkedListAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let outer = self.allocate(outer_layout(layout));
+        if outer.is_null() {
+            return outer;
+        }
+
+        let ptr = outer.add(front_size(layout.align()));
+        outer.write_bytes(CANARY, front_size(layout.align()));
+        ptr.add(layout.size()).write_bytes(CANARY, CANARY_SIZE);
+        header_of(ptr).write_unaligned(DebugHeader {
+            size: layout.size(),
+            align: layout.align(),
+        });
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Err(err) = self.check(ptr, layout) {
+            panic!("Heap corruption detected: {}", err);
+        }
+
+        let outer = ptr.sub(front_size(layout.align()));
+        outer.write_bytes(POISON, outer_layout(layout).size());
+        self.deallocate(outer, outer_layout(layout));
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if let Err(err) = self.check(ptr, layout) {
+            panic!("Heap corruption detected: {}", err);
+        }
+
+        // The front canary and the header keep their offset, only the back canary has to move
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let outer = self.reallocate(
+            ptr.sub(front_size(layout.align())),
+            outer_layout(layout),
+            outer_layout(new_layout).size(),
+        );
+        if outer.is_null() {
+            return outer;
+        }
+
+        let new_ptr = outer.add(front_size(layout.align()));
+        new_ptr.add(new_size).write_bytes(CANARY, CANARY_SIZE);
+        header_of(new_ptr).write_unaligned(DebugHeader {
+            size: new_size,
+            align: layout.align(),
+        });
+
+        new_ptr
+    }
+}
+
+impl LinkedListAllocator {
+    /// Checks that `ptr` is a live allocation made with `layout` and that its canaries are intact
+    ///
+    /// # Safety
+    /// The allocator has to be initialized and its node list must not be corrupted
+    pub unsafe fn check(&self, ptr: *mut u8, layout: Layout) -> Result<(), HeapError> {
+        let address = ptr as usize;
+        let node = self
+            .nodes()
+            .find(|n| (n.address..n.address + n.size).contains(&address));
+        let node = match node {
+            Some(node) if node.free => return Err(HeapError::DoubleFree { ptr: address }),
+            Some(node) => node,
+            None => return Err(HeapError::InvalidPointer { ptr: address }),
+        };
+
+        // Only read the header once it is known to be inside the allocation, it has to sit right
+        // behind the front canary that ends the node's padding
+        if address - node.address < CANARY_SIZE + size_of::<DebugHeader>() {
+            return Err(HeapError::InvalidPointer { ptr: address });
+        }
+        let header = header_of(ptr).read_unaligned();
+        if !header.align.is_power_of_two()
+            || address.wrapping_sub(front_size(header.align)) != node.address
+        {
+            return Err(HeapError::InvalidPointer { ptr: address });
+        }
+        let outer = node.address;
+
+        let allocated = Layout::from_size_align_unchecked(header.size, header.align);
+        if allocated != layout {
+            return Err(HeapError::LayoutMismatch {
+                ptr: address,
+                allocated,
+                freed: layout,
+            });
+        }
+
+        let front = outer..header_of(ptr) as usize;
+        let back = address - CANARY_SIZE..address + layout.size() + CANARY_SIZE;
+        for canary in front.chain(back) {
+            if (address..address + layout.size()).contains(&canary) {
+                continue;
+            }
+            if *(canary as *const u8) != CANARY {
+                return Err(HeapError::CorruptedCanary {
+                    ptr: address,
+                    address: canary,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn front_size(align: usize) -> usize {
+    (size_of::<DebugHeader>() + CANARY_SIZE).next_multiple_of(align)
+}
+
+fn outer_layout(layout: Layout) -> Layout {
+    let size = front_size(layout.align()) + layout.size() + CANARY_SIZE;
+    unsafe { Layout::from_size_align_unchecked(size, layout.align()) }
+}
+
+unsafe fn header_of(ptr: *mut u8) -> *mut DebugHeader {
+    ptr.sub(CANARY_SIZE + size_of::<DebugHeader>()) as *mut DebugHeader
+}
@@ -1,7 +1,26 @@
 #![cfg_attr(not(test), no_std)]
+#![cfg_attr(feature = "allocator-api", feature(allocator_api))]
 
-pub use self::allocator::LinkedListAllocator;
+pub use self::allocator::{GrowHandler, LinkedListAllocator};
+pub use self::buddy::{block_size, order_for, BuddyAllocator, MIN_BLOCK_SIZE, ORDERS};
+#[cfg(feature = "debug")]
+pub use self::debug::{HeapError, CANARY, CANARY_SIZE, POISON};
 pub use self::linked_list::{LinkedAllocatorIter, LinkedAllocatorNode};
+pub use self::locked::{
+    Locked, LockedBuddyAllocator, LockedLinkedListAllocator, LockedSlabAllocator,
+};
+pub use self::slab::{size_class, SlabAllocator, SIZE_CLASSES, SLAB_SIZE};
+pub use self::stats::{HeapStats, NodeInfo};
+#[cfg(feature = "track")]
+pub use self::track::{CallSite, CallSiteReport, TrackHandler, TrackedAllocation, BACKTRACE_DEPTH};
 
 pub(crate) mod allocator;
+pub(crate) mod buddy;
+#[cfg(feature = "debug")]
+pub(crate) mod debug;
 pub(crate) mod linked_list;
+pub(crate) mod locked;
+pub(crate) mod slab;
+pub(crate) mod stats;
+#[cfg(feature = "track")]
+pub(crate) mod track;
@@ -1,10 +1,28 @@
-use core::mem::size_of;
+use core::{mem::size_of, ptr::NonNull};
 
+#[cfg(feature = "track")]
+use crate::CallSite;
+
+// Every node has room for the footer it gets once it's freed
+pub(crate) const MIN_NODE_SIZE: usize = size_of::<usize>();
+
+// Free nodes carry a boundary tag at both ends, the node itself and a footer in their last bytes
+// that holds their size. The footer lets a node that is being freed find and merge with the node in
+// front of it without walking the list.
 #[derive(Debug)]
 pub struct LinkedAllocatorNode {
     pub size: usize,
     pub free: bool,
-    pub next: Option<*mut Self>,
+    // Whether the node directly in front of this one is free, its footer is only valid then
+    pub prev_free: bool,
+    // All nodes in address order, might point into a different region
+    pub next: Option<NonNull<Self>>,
+    // Doubly linked list of free nodes, only valid while the node is free
+    pub free_next: Option<NonNull<Self>>,
+    pub free_prev: Option<NonNull<Self>>,
+    // Where the allocation was made from, only valid while the node is used
+    #[cfg(feature = "track")]
+    pub site: CallSite,
 }
 
 impl LinkedAllocatorNode {
@@ -12,7 +30,12 @@ impl LinkedAllocatorNode {
         LinkedAllocatorNode {
             size: size - size_of::<Self>(),
             free: true,
+            prev_free: false,
             next: None,
+            free_next: None,
+            free_prev: None,
+            #[cfg(feature = "track")]
+            site: CallSite::default(),
         }
     }
 
@@ -25,6 +48,42 @@ impl LinkedAllocatorNode {
         self.start_address() + (self.size - 1)
     }
 
+    // Returns the next node if it directly follows this one
+    pub fn physical_next(&self) -> Option<&'static mut Self> {
+        self.next
+            .filter(|next| next.as_ptr() as usize == self.end_address() + 1)
+            .map(|mut next| unsafe { next.as_mut() })
+    }
+
+    /// Returns the node directly in front of this one if it is free, found through its footer
+    ///
+    /// # Safety
+    /// The boundary tags have to be intact
+    pub unsafe fn prev_if_free(&self) -> Option<&'static mut Self> {
+        if !self.prev_free {
+            return None;
+        }
+
+        let footer = (self as *const _ as usize - size_of::<usize>()) as *const usize;
+        let prev = self as *const _ as usize - footer.read_unaligned() - size_of::<Self>();
+        Some(&mut *(prev as *mut Self))
+    }
+
+    /// Writes the footer of a free node and tells the node behind it whether it can merge
+    /// backwards, has to be called whenever the size or the free bit changes
+    ///
+    /// # Safety
+    /// The node has to describe valid heap memory of at least `MIN_NODE_SIZE` bytes
+    pub unsafe fn update_tags(&mut self) {
+        if self.free {
+            let footer = (self.end_address() + 1 - size_of::<usize>()) as *mut usize;
+            footer.write_unaligned(self.size);
+        }
+        if let Some(next) = self.physical_next() {
+            next.prev_free = self.free;
+        }
+    }
+
     pub fn as_iter<'a>(&'a self) -> LinkedAllocatorIter {
         LinkedAllocatorIter {
             current: Some(self as *const _ as usize as *mut LinkedAllocatorNode),
@@ -43,7 +102,7 @@ impl Iterator for LinkedAllocatorIter {
         let current = self.current?;
 
         let deref = unsafe { &mut (*current) };
-        self.current = deref.next;
+        self.current = deref.next.map(NonNull::as_ptr);
 
         Some(deref)
     }
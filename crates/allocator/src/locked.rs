@@ -0,0 +1,187 @@
+use core::alloc::{GlobalAlloc, Layout};
+#[cfg(feature = "allocator-api")]
+use core::{
+    alloc::{AllocError, Allocator},
+    ptr::{self, NonNull},
+};
+
+use spin::{Mutex, MutexGuard};
+
+use crate::{BuddyAllocator, LinkedListAllocator, SlabAllocator};
+
+pub type LockedBuddyAllocator = Locked<BuddyAllocator>;
+pub type LockedLinkedListAllocator = Locked<LinkedListAllocator>;
+pub type LockedSlabAllocator = Locked<SlabAllocator>;
+
+pub struct Locked<A> {
+    inner: Mutex<A>,
+}
+
+impl<A> Locked<A> {
+    // Interrupts are not disabled while the guard is held, use `with` if an interrupt handler
+    // could allocate in the meantime
+    pub fn lock(&self) -> MutexGuard<'_, A> {
+        self.inner.lock()
+    }
+
+    pub fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut A) -> R,
+    {
+        without_interrupts(|| f(&mut self.inner.lock()))
+    }
+}
+
+impl Locked<LinkedListAllocator> {
+    pub const fn new() -> Self {
+        Locked {
+            inner: Mutex::new(LinkedListAllocator::new()),
+        }
+    }
+
+    /// Creates an allocator that is independent of the global heap and only hands out memory from
+    /// `start..start + size`, e.g. for a subsystem that shouldn't be able to exhaust the heap
+    ///
+    /// # Safety
+    /// See [`LinkedListAllocator::add_region`]
+    pub unsafe fn from_region(start: usize, size: usize) -> Self {
+        let mut allocator = LinkedListAllocator::new();
+        allocator.add_region(start, size);
+        Locked {
+            inner: Mutex::new(allocator),
+        }
+    }
+}
+
+impl Default for Locked<LinkedListAllocator> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Locked<SlabAllocator> {
+    pub const fn new() -> Self {
+        Locked {
+            inner: Mutex::new(SlabAllocator::new()),
+        }
+    }
+}
+
+impl Default for Locked<SlabAllocator> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Locked<BuddyAllocator> {
+    pub const fn new() -> Self {
+        Locked {
+            inner: Mutex::new(BuddyAllocator::new()),
+        }
+    }
+}
+
+impl Default for Locked<BuddyAllocator> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Locked<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with(|allocator| allocator.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.with(|allocator| allocator.dealloc(ptr, layout))
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.with(|allocator| allocator.realloc(ptr, layout, new_size))
+    }
+}
+
+// Lets collections use a heap directly, e.g. `Vec::new_in(&heap)`. Zero sized allocations never
+// reach the inner allocator, GlobalAlloc doesn't allow them
+#[cfg(feature = "allocator-api")]
+unsafe impl<A: GlobalAlloc> Allocator for Locked<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            return Ok(NonNull::slice_from_raw_parts(dangling(layout), 0));
+        }
+
+        let ptr = NonNull::new(unsafe { self.alloc(layout) }).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            self.dealloc(ptr.as_ptr(), layout);
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+}
+
+#[cfg(feature = "allocator-api")]
+impl<A: GlobalAlloc> Locked<A> {
+    // Resizes in place through `realloc` if the alignment stays the same, otherwise moves the
+    // allocation
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if old_layout.size() != 0
+            && new_layout.size() != 0
+            && old_layout.align() == new_layout.align()
+        {
+            let new_ptr = NonNull::new(self.realloc(ptr.as_ptr(), old_layout, new_layout.size()))
+                .ok_or(AllocError)?;
+            return Ok(NonNull::slice_from_raw_parts(new_ptr, new_layout.size()));
+        }
+
+        let new_ptr = self.allocate(new_layout)?;
+        ptr::copy_nonoverlapping(
+            ptr.as_ptr(),
+            new_ptr.cast::<u8>().as_ptr(),
+            old_layout.size().min(new_layout.size()),
+        );
+        self.deallocate(ptr, old_layout);
+        Ok(new_ptr)
+    }
+}
+
+#[cfg(feature = "allocator-api")]
+fn dangling(layout: Layout) -> NonNull<u8> {
+    unsafe { NonNull::new_unchecked(ptr::without_provenance_mut(layout.align())) }
+}
+
+// Holding the lock while an interrupt handler tries to allocate would deadlock the core, so
+// interrupts are masked for as long as the allocator is locked
+#[cfg(target_os = "none")]
+fn without_interrupts<F: FnOnce() -> R, R>(f: F) -> R {
+    x86_64::instructions::interrupts::without_interrupts(f)
+}
+
+#[cfg(not(target_os = "none"))]
+fn without_interrupts<F: FnOnce() -> R, R>(f: F) -> R {
+    f()
+}
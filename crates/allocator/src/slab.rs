@@ -0,0 +1,168 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::Cell,
+    mem::size_of,
+    ptr::{copy_nonoverlapping, null_mut},
+};
+
+#[cfg(feature = "track")]
+use crate::{CallSiteReport, TrackHandler};
+use crate::{GrowHandler, HeapStats, LinkedAllocatorNode, LinkedListAllocator, NodeInfo};
+
+// Slabs are carved out of the fallback allocator, each slab only holds blocks of one size class
+pub const SLAB_SIZE: usize = 4096;
+pub const SIZE_CLASSES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+struct FreeBlock {
+    next: Option<*mut FreeBlock>,
+}
+
+// Small allocations are served from per size class free lists, everything bigger than the
+// largest class goes straight to the linked list allocator. Slabs are never returned to the
+// linked list, freed blocks are only reused by their size class.
+pub struct SlabAllocator {
+    free_blocks: [Cell<Option<*mut FreeBlock>>; SIZE_CLASSES.len()],
+    fallback: LinkedListAllocator,
+}
+
+unsafe impl Send for SlabAllocator {}
+
+unsafe impl GlobalAlloc for SlabAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(class) = size_class(layout) else {
+            return self.fallback.alloc(layout);
+        };
+
+        if self.free_blocks[class].get().is_none() && !self.add_slab(class) {
+            return null_mut();
+        }
+
+        let block = self.free_blocks[class].get().unwrap();
+        self.free_blocks[class].set((*block).next);
+        block as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let Some(class) = size_class(layout) else {
+            return self.fallback.dealloc(ptr, layout);
+        };
+
+        let block = &mut *(ptr as *mut FreeBlock);
+        block.next = self.free_blocks[class].get();
+        self.free_blocks[class].set(Some(block));
+    }
+
+    // Allocations too big for a size class stay in the linked list, which can often resize them in
+    // place
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if size_class(layout).is_none() && size_class(new_layout).is_none() {
+            return self.fallback.realloc(ptr, layout, new_size);
+        }
+
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+impl SlabAllocator {
+    pub const fn new() -> SlabAllocator {
+        SlabAllocator {
+            free_blocks: [const { Cell::new(None) }; SIZE_CLASSES.len()],
+            fallback: LinkedListAllocator::new(),
+        }
+    }
+
+    pub fn init(&mut self, node: &'static mut LinkedAllocatorNode) {
+        self.fallback.init(node);
+    }
+
+    /// # Safety
+    /// See [`LinkedListAllocator::add_region`]
+    pub unsafe fn add_region(&mut self, start: usize, size: usize) {
+        self.fallback.add_region(start, size);
+    }
+
+    pub fn set_grow_handler(&mut self, handler: GrowHandler) {
+        self.fallback.set_grow_handler(handler);
+    }
+
+    // Only slabs and allocations too big for a size class are tracked, blocks inside of a slab
+    // show up under the caller that made the slab
+    #[cfg(feature = "track")]
+    pub fn set_track_handler(&mut self, handler: TrackHandler) {
+        self.fallback.set_track_handler(handler);
+    }
+
+    #[cfg(feature = "track")]
+    pub fn leak_report<'r>(
+        &self,
+        reports: &'r mut [CallSiteReport],
+    ) -> (&'r [CallSiteReport], usize) {
+        self.fallback.leak_report(reports)
+    }
+
+    pub fn fallback(&self) -> &LinkedListAllocator {
+        &self.fallback
+    }
+
+    // Slabs show up as used nodes of the fallback allocator
+    pub fn stats(&self) -> HeapStats {
+        self.fallback.stats()
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = NodeInfo> + '_ {
+        self.fallback.nodes()
+    }
+
+    // Returns how many free blocks the size class at `class` currently has
+    pub fn free_blocks(&self, class: usize) -> usize {
+        self.free_list(class).count()
+    }
+
+    // Addresses of the free blocks of the size class at `class`, in the order they get handed out
+    pub fn free_list(&self, class: usize) -> impl Iterator<Item = usize> + '_ {
+        let mut current = self.free_blocks[class].get();
+        core::iter::from_fn(move || {
+            let block = current?;
+            current = unsafe { (*block).next };
+            Some(block as usize)
+        })
+    }
+
+    // Splits a new slab from the fallback allocator into blocks of the size class
+    unsafe fn add_slab(&self, class: usize) -> bool {
+        let slab = self
+            .fallback
+            .alloc(Layout::from_size_align_unchecked(SLAB_SIZE, SLAB_SIZE));
+        if slab.is_null() {
+            return false;
+        }
+
+        for offset in (0..SLAB_SIZE).step_by(SIZE_CLASSES[class]).rev() {
+            let block = &mut *(slab.add(offset) as *mut FreeBlock);
+            block.next = self.free_blocks[class].get();
+            self.free_blocks[class].set(Some(block));
+        }
+        true
+    }
+}
+
+impl Default for SlabAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Blocks are aligned to their size inside a slab, so the class has to cover the alignment as well
+pub fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout
+        .size()
+        .max(layout.align())
+        .max(size_of::<FreeBlock>());
+    SIZE_CLASSES.iter().position(|&class| class >= size)
+}
@@ -0,0 +1,76 @@
+use crate::{LinkedAllocatorNode, LinkedListAllocator};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    // Bytes covered by the heap, including node tags
+    pub total: usize,
+    pub used: usize,
+    pub free: usize,
+    pub nodes: usize,
+    pub largest_free: usize,
+    // Live allocations, i.e. nodes that aren't free
+    pub allocations: usize,
+}
+
+impl HeapStats {
+    // How much of the free memory can't be handed out in one piece, 0 when all free memory is in
+    // a single node and close to 100 when it is scattered in many small ones
+    pub fn fragmentation(&self) -> usize {
+        if self.free == 0 {
+            return 0;
+        }
+        100 - self.largest_free * 100 / self.free
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeInfo {
+    // Address of the memory described by the node (what `alloc` returned for used nodes)
+    pub address: usize,
+    pub size: usize,
+    pub free: bool,
+}
+
+impl From<&LinkedAllocatorNode> for NodeInfo {
+    fn from(node: &LinkedAllocatorNode) -> Self {
+        NodeInfo {
+            address: node.start_address(),
+            size: node.size,
+            free: node.free,
+        }
+    }
+}
+
+impl LinkedListAllocator {
+    pub fn nodes(&self) -> impl Iterator<Item = NodeInfo> + '_ {
+        self.nodes
+            .into_iter()
+            .flat_map(|node| unsafe { (*node).as_iter() })
+            .map(|node| NodeInfo::from(&*node))
+    }
+
+    // The free list, in the order allocation searches it
+    pub fn free_nodes(&self) -> impl Iterator<Item = NodeInfo> + '_ {
+        let mut current = self.free_list.get();
+        core::iter::from_fn(move || {
+            let node = unsafe { current?.as_ref() };
+            current = node.free_next;
+            Some(NodeInfo::from(node))
+        })
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        self.nodes().fold(HeapStats::default(), |mut stats, node| {
+            stats.total += node.size + core::mem::size_of::<LinkedAllocatorNode>();
+            stats.nodes += 1;
+            if node.free {
+                stats.free += node.size;
+                stats.largest_free = stats.largest_free.max(node.size);
+            } else {
+                stats.used += node.size;
+                stats.allocations += 1;
+            }
+            stats
+        })
+    }
+}
@@ -0,0 +1,110 @@
+use core::mem::size_of;
+
+use crate::{LinkedAllocatorNode, LinkedListAllocator};
+
+// Return addresses recorded above the caller of an allocation
+pub const BACKTRACE_DEPTH: usize = 4;
+
+// Where a live allocation was made from, stored in its node
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallSite {
+    // Address the allocation was made from, allocations are grouped by it
+    pub caller: usize,
+    // Callers above `caller`, 0 once the stack ends
+    pub backtrace: [usize; BACKTRACE_DEPTH],
+    pub timestamp: u64,
+}
+
+// Called for every allocation, returns the call site to record for it
+pub type TrackHandler = fn() -> CallSite;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackedAllocation {
+    pub address: usize,
+    pub size: usize,
+    pub site: CallSite,
+}
+
+impl From<&LinkedAllocatorNode> for TrackedAllocation {
+    fn from(node: &LinkedAllocatorNode) -> Self {
+        TrackedAllocation {
+            address: node.start_address(),
+            size: node.size,
+            site: node.site,
+        }
+    }
+}
+
+// Outstanding allocations of a single caller
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallSiteReport {
+    pub caller: usize,
+    pub allocations: usize,
+    pub bytes: usize,
+    // The oldest allocation is usually the one that leaked
+    pub oldest: CallSite,
+}
+
+impl LinkedListAllocator {
+    pub fn set_track_handler(&mut self, handler: TrackHandler) {
+        self.track_handler = Some(handler);
+    }
+
+    // Every live allocation in address order
+    pub fn tracked(&self) -> impl Iterator<Item = TrackedAllocation> + '_ {
+        self.nodes
+            .into_iter()
+            .flat_map(|node| unsafe { (*node).as_iter() })
+            .filter(|node| !node.free)
+            .map(|node| TrackedAllocation::from(&*node))
+    }
+
+    // Groups the live allocations by caller into `reports` in a single walk over the heap, the
+    // allocator can't allocate memory for them itself. Returns the reports sorted by caller and how
+    // many allocations were left out because their caller didn't fit into `reports` anymore.
+    pub fn leak_report<'r>(
+        &self,
+        reports: &'r mut [CallSiteReport],
+    ) -> (&'r [CallSiteReport], usize) {
+        let mut len = 0;
+        let mut skipped = 0;
+        for allocation in self.tracked() {
+            let caller = allocation.site.caller;
+            let i = match reports[..len].binary_search_by_key(&caller, |r| r.caller) {
+                Ok(i) => i,
+                Err(_) if len == reports.len() => {
+                    skipped += 1;
+                    continue;
+                }
+                Err(i) => {
+                    reports[i..=len].rotate_right(1);
+                    reports[i] = CallSiteReport {
+                        caller,
+                        allocations: 0,
+                        bytes: 0,
+                        oldest: allocation.site,
+                    };
+                    len += 1;
+                    i
+                }
+            };
+
+            let report = &mut reports[i];
+            report.allocations += 1;
+            report.bytes += allocation.size;
+            if allocation.site.timestamp < report.oldest.timestamp {
+                report.oldest = allocation.site;
+            }
+        }
+        (&reports[..len], skipped)
+    }
+
+    // Records the call site of a fresh allocation in its node
+    pub(crate) unsafe fn track(&self, ptr: *mut u8) {
+        if let Some(handler) = self.track_handler {
+            let node =
+                &mut *(ptr.sub(size_of::<LinkedAllocatorNode>()) as *mut LinkedAllocatorNode);
+            node.site = handler();
+        }
+    }
+}
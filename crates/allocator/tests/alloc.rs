@@ -1,5 +1,6 @@
 use allocator::*;
 use core::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 pub unsafe fn print_nodes(alloc: &LinkedListAllocator) {
     if alloc.nodes.is_none() {
@@ -12,6 +13,11 @@ pub unsafe fn print_nodes(alloc: &LinkedListAllocator) {
     }
 }
 
+// Heap memory with a fixed alignment, so the padding inserted by the allocator doesn't depend on where
+// the stack of the test happens to be
+#[repr(C, align(64))]
+struct AlignedBuf<const N: usize>([u8; N]);
+
 fn create_node(ptr: usize, size: usize) -> *mut LinkedAllocatorNode {
     let node: &mut LinkedAllocatorNode = unsafe { std::mem::transmute(ptr) };
     node.next = None;
@@ -79,6 +85,9 @@ fn should_merge_with_previous_node() {
     assert_eq!(node.next, None);
 }
 
+// The debug and track features add their own data to every allocation, which changes the node
+// layout
+#[cfg(not(any(feature = "debug", feature = "track")))]
 #[test]
 fn should_merge_with_next_node() {
     const SIZE: usize = 256;
@@ -114,6 +123,7 @@ fn should_merge_with_next_node() {
     }
 }
 
+#[cfg(not(any(feature = "debug", feature = "track")))]
 #[test]
 fn should_merge_with_next_and_previous_node() {
     const SIZE: usize = 256;
@@ -157,6 +167,79 @@ fn should_merge_with_next_and_previous_node() {
     assert_eq!(node.next, None);
 }
 
+// Same as the tests above, but the heap doesn't depend on where the stack of the test is, so the
+// allocations always need padding and the nodes are only found through their boundary tags
+#[cfg(not(any(feature = "debug", feature = "track")))]
+#[test]
+fn should_merge_with_next_node_through_boundary_tags() {
+    const SIZE: usize = 512;
+    let mut buf = AlignedBuf([0u8; SIZE + 8]);
+    let node = create_node(&mut buf.0[8] as *const _ as usize, SIZE);
+
+    let node = unsafe { &mut *node };
+
+    let mut allocator = LinkedListAllocator::new();
+    allocator.init(node);
+
+    unsafe {
+        let ptr1 = allocator.alloc(Layout::from_size_align_unchecked(32, 32));
+        let _ptr2 = allocator.alloc(Layout::from_size_align_unchecked(32, 1));
+
+        // Start, ptr2, ptr1, reserved
+        assert_eq!(allocator.nodes().count(), 4);
+
+        allocator.dealloc(ptr1, Layout::from_size_align_unchecked(32, 32));
+
+        // Start, ptr2, free, the freed node reaches the end of the heap again
+        let node = &*(allocator.nodes_mut().unwrap());
+        let nodes: Vec<_> = node.as_iter().collect();
+        assert_eq!(nodes.len(), 3);
+        assert!(nodes[0].free);
+        assert!(!nodes[1].free);
+        assert!(nodes[2].free);
+        assert!(!nodes[2].prev_free);
+        assert_eq!(
+            nodes[2].end_address() + 1,
+            buf.0.as_ptr() as usize + 8 + SIZE
+        );
+    }
+}
+
+#[cfg(not(any(feature = "debug", feature = "track")))]
+#[test]
+fn should_merge_with_next_and_previous_node_through_boundary_tags() {
+    const SIZE: usize = 512;
+    let mut buf = AlignedBuf([0u8; SIZE + 8]);
+    let node = create_node(&mut buf.0[8] as *const _ as usize, SIZE);
+
+    let node = unsafe { &mut *node };
+
+    let mut allocator = LinkedListAllocator::new();
+    allocator.init(node);
+
+    unsafe {
+        let ptr1 = allocator.alloc(Layout::from_size_align_unchecked(32, 32));
+        let ptr2 = allocator.alloc(Layout::from_size_align_unchecked(32, 1));
+
+        // Start, ptr2, ptr1, reserved
+        assert_eq!(allocator.nodes().count(), 4);
+
+        allocator.dealloc(ptr1, Layout::from_size_align_unchecked(32, 32));
+
+        // Start, ptr2, free
+        assert_eq!(allocator.nodes().count(), 3);
+
+        allocator.dealloc(ptr2, Layout::from_size_align_unchecked(32, 1));
+    }
+
+    // free
+    let node = unsafe { &*(allocator.nodes_mut().unwrap()) };
+    assert!(node.free);
+    assert_eq!(node.size, SIZE - size_of::<LinkedAllocatorNode>());
+    assert_eq!(node.next, None);
+}
+
+#[cfg(not(any(feature = "debug", feature = "track")))]
 #[test]
 fn should_merge_after_multiple_allocations() {
     const SIZE: usize = 512;
@@ -194,8 +277,8 @@ fn should_merge_after_multiple_allocations() {
     assert_eq!(node.next, None);
 }
 
+#[cfg(not(any(feature = "debug", feature = "track")))]
 #[test]
-#[should_panic]
 fn should_fail_allocating_too_big_chunk() {
     const SIZE: usize = 64;
     let mut buf = [0u8; SIZE];
@@ -209,9 +292,17 @@ fn should_fail_allocating_too_big_chunk() {
     unsafe {
         let layout1 = Layout::from_size_align_unchecked(256, 2);
         let ptr1 = allocator.alloc(layout1);
+        assert!(ptr1.is_null());
     }
+
+    // Heap is left untouched
+    let node = unsafe { &*(allocator.nodes_mut().unwrap()) };
+    assert!(node.free);
+    assert_eq!(node.size, SIZE - size_of::<LinkedAllocatorNode>());
+    assert_eq!(node.next, None);
 }
 
+#[cfg(not(any(feature = "debug", feature = "track")))]
 #[test]
 fn should_allocate_exact_size() {
     const SIZE: usize = size_of::<LinkedAllocatorNode>() + 8;
@@ -239,3 +330,703 @@ fn should_allocate_exact_size() {
     assert_eq!(node.size, SIZE - size_of::<LinkedAllocatorNode>());
     assert_eq!(node.next, None);
 }
+
+#[test]
+fn should_serialize_locked_allocations() {
+    const SIZE: usize = 4096;
+    let mut buf = [0u8; SIZE];
+    let node = unsafe { &mut *create_node(&mut buf[0] as *const _ as usize, SIZE) };
+
+    let allocator = LockedLinkedListAllocator::new();
+    allocator.lock().init(node);
+
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| unsafe {
+                let layout = Layout::from_size_align_unchecked(16, 8);
+                for _ in 0..100 {
+                    let ptr = allocator.alloc(layout);
+                    assert_eq!(ptr as usize % 8, 0);
+                    allocator.dealloc(ptr, layout);
+                }
+            });
+        }
+    });
+
+    let allocator = allocator.lock();
+    let node = unsafe { &*(allocator.nodes_mut().unwrap()) };
+    assert!(node.free);
+    assert_eq!(node.size, SIZE - size_of::<LinkedAllocatorNode>());
+    assert_eq!(node.next, None);
+}
+
+static GROWABLE_HEAP_END: AtomicUsize = AtomicUsize::new(0);
+
+fn grow_by_chunks(heap_end: usize, min_size: usize) -> usize {
+    const CHUNK: usize = 128;
+    let size = min_size.div_ceil(CHUNK) * CHUNK;
+
+    if heap_end + size > GROWABLE_HEAP_END.load(Ordering::SeqCst) {
+        return 0;
+    }
+    size
+}
+
+#[test]
+fn should_grow_heap_when_full() {
+    const SIZE: usize = 2048;
+    let mut buf = [0u64; SIZE / 8];
+    let start = &mut buf[0] as *mut _ as usize;
+    GROWABLE_HEAP_END.store(start + SIZE, Ordering::SeqCst);
+
+    let node = unsafe { &mut *create_node(start, 128) };
+
+    let mut allocator = LinkedListAllocator::new();
+    allocator.init(node);
+    allocator.set_grow_handler(grow_by_chunks);
+
+    unsafe {
+        let layout = Layout::from_size_align_unchecked(256, 8);
+        let ptr1 = allocator.alloc(layout);
+        let ptr2 = allocator.alloc(layout);
+        assert!(allocator.heap_end().unwrap() > start + 128);
+        assert!(allocator.heap_end().unwrap() <= start + SIZE);
+
+        allocator.dealloc(ptr1, layout);
+        allocator.dealloc(ptr2, layout);
+    }
+
+    // Grown space is merged back into one free node
+    let heap_end = allocator.heap_end().unwrap();
+    let node = unsafe { &*(allocator.nodes_mut().unwrap()) };
+    assert!(node.free);
+    assert_eq!(
+        node.size,
+        heap_end - start - size_of::<LinkedAllocatorNode>()
+    );
+    assert_eq!(node.next, None);
+}
+
+#[test]
+fn should_fail_growing_past_limit() {
+    const SIZE: usize = 512;
+    let mut buf = [0u64; SIZE / 8];
+    let start = &mut buf[0] as *mut _ as usize;
+    GROWABLE_HEAP_END.store(start + SIZE, Ordering::SeqCst);
+
+    let node = unsafe { &mut *create_node(start, 128) };
+
+    let mut allocator = LinkedListAllocator::new();
+    allocator.init(node);
+    allocator.set_grow_handler(grow_by_chunks);
+
+    unsafe {
+        let ptr = allocator.alloc(Layout::from_size_align_unchecked(1024, 8));
+        assert!(ptr.is_null());
+    }
+}
+
+#[test]
+fn should_return_null_when_out_of_memory() {
+    const SIZE: usize = 512;
+    let mut buf = AlignedBuf([0u8; SIZE]);
+    let node = unsafe { &mut *create_node(&mut buf.0[0] as *const _ as usize, SIZE) };
+
+    let allocator = LockedLinkedListAllocator::new();
+    allocator.lock().init(node);
+
+    unsafe {
+        let big = Layout::from_size_align_unchecked(4096, 8);
+        assert!(allocator.alloc(big).is_null());
+
+        // The failed allocation doesn't prevent smaller ones
+        let small = Layout::from_size_align_unchecked(64, 8);
+        let ptr = allocator.alloc(small);
+        assert!(!ptr.is_null());
+        allocator.dealloc(ptr, small);
+    }
+}
+
+#[test]
+fn should_serve_small_allocations_from_slab() {
+    const SIZE: usize = 3 * SLAB_SIZE;
+    let mut buf = AlignedBuf([0u8; SIZE]);
+    let node = unsafe { &mut *create_node(&mut buf.0[0] as *const _ as usize, SIZE) };
+
+    let mut allocator = SlabAllocator::new();
+    allocator.init(node);
+
+    let layout = Layout::from_size_align(12, 4).unwrap();
+    let class = size_class(layout).unwrap();
+    assert_eq!(SIZE_CLASSES[class], 16);
+
+    unsafe {
+        let ptrs: Vec<*mut u8> = (0..3).map(|_| allocator.alloc(layout)).collect();
+        for ptr in &ptrs {
+            assert_eq!(*ptr as usize % 16, 0);
+            assert_eq!(*ptr as usize / SLAB_SIZE, ptrs[0] as usize / SLAB_SIZE);
+        }
+        assert_eq!(allocator.free_blocks(class), SLAB_SIZE / 16 - 3);
+
+        for ptr in ptrs {
+            allocator.dealloc(ptr, layout);
+        }
+    }
+
+    assert_eq!(allocator.free_blocks(class), SLAB_SIZE / 16);
+}
+
+#[test]
+fn should_fall_back_to_linked_list_for_large_allocations() {
+    const SIZE: usize = 2 * SLAB_SIZE;
+    let mut buf = AlignedBuf([0u8; SIZE]);
+    let node = unsafe { &mut *create_node(&mut buf.0[0] as *const _ as usize, SIZE) };
+
+    let mut allocator = SlabAllocator::new();
+    allocator.init(node);
+
+    let layout = Layout::from_size_align(4000, 8).unwrap();
+    assert!(size_class(layout).is_none());
+
+    unsafe {
+        let ptr = allocator.alloc(layout);
+        assert!(!ptr.is_null());
+        assert!((0..SIZE_CLASSES.len()).all(|class| allocator.free_blocks(class) == 0));
+
+        allocator.dealloc(ptr, layout);
+    }
+
+    let node = unsafe { &*(allocator.fallback().nodes_mut().unwrap()) };
+    assert!(node.free);
+    assert_eq!(node.size, SIZE - size_of::<LinkedAllocatorNode>());
+    assert_eq!(node.next, None);
+}
+
+#[test]
+fn should_realloc_large_allocations_in_linked_list() {
+    const SIZE: usize = 6 * SLAB_SIZE;
+    let mut buf = AlignedBuf([0u8; SIZE]);
+    let node = unsafe { &mut *create_node(&mut buf.0[0] as *const _ as usize, SIZE) };
+
+    let mut allocator = SlabAllocator::new();
+    allocator.init(node);
+
+    unsafe {
+        let layout = Layout::from_size_align_unchecked(4000, 8);
+        let behind = allocator.alloc(layout);
+        let ptr = allocator.alloc(layout);
+        ptr.write_bytes(0xab, 4000);
+
+        // The free block behind it lets the linked list grow it in place
+        allocator.dealloc(behind, layout);
+        let grown = allocator.realloc(ptr, layout, 8000);
+        assert_eq!(grown, ptr);
+        let data = std::slice::from_raw_parts(grown, 4000);
+        assert!(data.iter().all(|b| *b == 0xab));
+
+        // Moving into a size class copies it into a slab
+        let large = Layout::from_size_align_unchecked(8000, 8);
+        let shrunk = allocator.realloc(grown, large, 16);
+        let class = size_class(Layout::from_size_align_unchecked(16, 8)).unwrap();
+        assert_eq!(allocator.free_blocks(class), SLAB_SIZE / 16 - 1);
+        let data = std::slice::from_raw_parts(shrunk, 16);
+        assert!(data.iter().all(|b| *b == 0xab));
+
+        allocator.dealloc(shrunk, Layout::from_size_align_unchecked(16, 8));
+    }
+}
+
+// Backends that can be set up over a plain region of memory, so the cases below can run against
+// every one of them
+trait TestHeap: GlobalAlloc {
+    fn over_region(start: usize, size: usize) -> Self;
+}
+
+impl TestHeap for LinkedListAllocator {
+    fn over_region(start: usize, size: usize) -> Self {
+        let mut allocator = LinkedListAllocator::new();
+        allocator.init(unsafe { &mut *create_node(start, size) });
+        allocator
+    }
+}
+
+impl TestHeap for SlabAllocator {
+    fn over_region(start: usize, size: usize) -> Self {
+        let mut allocator = SlabAllocator::new();
+        allocator.init(unsafe { &mut *create_node(start, size) });
+        allocator
+    }
+}
+
+impl TestHeap for BuddyAllocator {
+    fn over_region(start: usize, size: usize) -> Self {
+        let mut allocator = BuddyAllocator::new();
+        unsafe { allocator.init(start, size) };
+        allocator
+    }
+}
+
+#[cfg(not(feature = "debug"))]
+const BACKEND_HEAP_SIZE: usize = 64 * 1024;
+// Slabs are page aligned, so the debug feature puts a whole page of padding in front of each one
+#[cfg(feature = "debug")]
+const BACKEND_HEAP_SIZE: usize = 128 * 1024;
+const BACKEND_LAYOUTS: [(usize, usize); 10] = [
+    (1, 1),
+    (24, 8),
+    (64, 64),
+    (100, 4),
+    (256, 16),
+    (7, 2),
+    (512, 512),
+    (48, 8),
+    (3000, 8),
+    (4096, 4096),
+];
+
+fn allocate_all<H: TestHeap>(heap: &H) -> Vec<(*mut u8, Layout)> {
+    let mut allocations = Vec::new();
+    for (size, align) in BACKEND_LAYOUTS.iter().chain(BACKEND_LAYOUTS.iter().rev()) {
+        let layout = Layout::from_size_align(*size, *align).unwrap();
+        let ptr = unsafe { heap.alloc(layout) };
+        assert!(!ptr.is_null(), "could not allocate {layout:?}");
+        assert_eq!(ptr as usize % align, 0);
+
+        unsafe { ptr.write_bytes(allocations.len() as u8, *size) };
+        allocations.push((ptr, layout));
+    }
+    allocations
+}
+
+fn free_all<H: TestHeap>(heap: &H, allocations: Vec<(*mut u8, Layout)>) {
+    for (i, (ptr, layout)) in allocations.into_iter().enumerate() {
+        // Nothing else wrote into the allocation
+        let data = unsafe { std::slice::from_raw_parts(ptr, layout.size()) };
+        assert!(data.iter().all(|b| *b == i as u8));
+
+        unsafe { heap.dealloc(ptr, layout) };
+    }
+}
+
+fn allocations_are_aligned_and_disjoint<H: TestHeap>() {
+    let mut buf = AlignedBuf([0u8; BACKEND_HEAP_SIZE]);
+    let heap = H::over_region(&mut buf.0[0] as *mut _ as usize, BACKEND_HEAP_SIZE);
+
+    let allocations = allocate_all(&heap);
+    for (i, (a, a_layout)) in allocations.iter().enumerate() {
+        for (b, b_layout) in &allocations[i + 1..] {
+            let (a, b) = (*a as usize, *b as usize);
+            assert!(a + a_layout.size() <= b || b + b_layout.size() <= a);
+        }
+    }
+    free_all(&heap, allocations);
+}
+
+fn freed_memory_is_reused<H: TestHeap>() {
+    let mut buf = AlignedBuf([0u8; BACKEND_HEAP_SIZE]);
+    let heap = H::over_region(&mut buf.0[0] as *mut _ as usize, BACKEND_HEAP_SIZE);
+
+    // Wouldn't fit into the heap at once
+    for _ in 0..8 {
+        let allocations = allocate_all(&heap);
+        free_all(&heap, allocations);
+    }
+}
+
+// Frees every other small allocation and returns the biggest block that can still be allocated
+fn largest_block_after_fragmenting<H: TestHeap>() -> usize {
+    let mut buf = AlignedBuf([0u8; BACKEND_HEAP_SIZE]);
+    let heap = H::over_region(&mut buf.0[0] as *mut _ as usize, BACKEND_HEAP_SIZE);
+    let small = Layout::from_size_align(64, 8).unwrap();
+
+    let allocations: Vec<*mut u8> = (0..BACKEND_HEAP_SIZE / 256)
+        .map(|_| unsafe { heap.alloc(small) })
+        .collect();
+    for ptr in allocations.iter().step_by(2) {
+        unsafe { heap.dealloc(*ptr, small) };
+    }
+
+    let mut largest = 0;
+    let mut size = 8;
+    while size <= BACKEND_HEAP_SIZE {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        let ptr = unsafe { heap.alloc(layout) };
+        if ptr.is_null() {
+            break;
+        }
+        unsafe { heap.dealloc(ptr, layout) };
+        largest = size;
+        size *= 2;
+    }
+    largest
+}
+
+macro_rules! backend_tests {
+    ($($name:ident => $heap:ty),* $(,)?) => {$(
+        mod $name {
+            use super::*;
+
+            #[test]
+            fn should_return_aligned_disjoint_allocations() {
+                allocations_are_aligned_and_disjoint::<$heap>();
+            }
+
+            #[test]
+            fn should_reuse_freed_memory() {
+                freed_memory_is_reused::<$heap>();
+            }
+        }
+    )*};
+}
+
+backend_tests! {
+    linked_list_backend => LinkedListAllocator,
+    slab_backend => SlabAllocator,
+    buddy_backend => BuddyAllocator,
+}
+
+#[test]
+fn should_report_fragmentation_of_backends() {
+    // Run with --nocapture to compare the backends
+    let backends = [
+        (
+            "linked list",
+            largest_block_after_fragmenting::<LinkedListAllocator>(),
+        ),
+        ("slab", largest_block_after_fragmenting::<SlabAllocator>()),
+        ("buddy", largest_block_after_fragmenting::<BuddyAllocator>()),
+    ];
+    for (name, largest) in backends {
+        println!("{name}: largest block after fragmenting the heap is {largest} bytes");
+        assert!(largest > 0);
+    }
+}
+
+#[test]
+fn should_coalesce_buddies() {
+    const SIZE: usize = 8192;
+    let mut buf = AlignedBuf([0u8; SIZE]);
+    let mut allocator = BuddyAllocator::new();
+    unsafe { allocator.init(&mut buf.0[0] as *mut _ as usize, SIZE) };
+
+    let blocks_before: Vec<usize> = (0..ORDERS).map(|o| allocator.free_blocks(o)).collect();
+
+    unsafe {
+        // The free block header fits the smallest blocks
+        let layout = Layout::from_size_align(MIN_BLOCK_SIZE, 8).unwrap();
+        assert_eq!(order_for(layout), Some(0));
+        let ptrs: Vec<*mut u8> = (0..6).map(|_| allocator.alloc(layout)).collect();
+        assert_eq!(allocator.free_blocks(0), 1);
+
+        for ptr in ptrs.into_iter().rev() {
+            allocator.dealloc(ptr, layout);
+        }
+    }
+
+    let blocks_after: Vec<usize> = (0..ORDERS).map(|o| allocator.free_blocks(o)).collect();
+    assert_eq!(blocks_before, blocks_after);
+}
+
+#[test]
+fn should_align_buddy_blocks_naturally() {
+    const SIZE: usize = 16 * 1024;
+    let mut buf = AlignedBuf([0u8; SIZE]);
+    let mut allocator = BuddyAllocator::new();
+    unsafe { allocator.init(&mut buf.0[0] as *mut _ as usize, SIZE) };
+
+    for size in [32, 100, 256, 1000, 2048] {
+        let layout = Layout::from_size_align(size, 1).unwrap();
+        let order = order_for(layout).unwrap();
+        assert!(block_size(order) >= size);
+
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert_eq!(ptr as usize % block_size(order), 0);
+    }
+}
+
+fn free_bytes(allocator: &LinkedListAllocator) -> usize {
+    let node = unsafe { &*(allocator.nodes_mut().unwrap()) };
+    node.as_iter().filter(|n| n.free).map(|n| n.size).sum()
+}
+
+#[test]
+fn should_grow_allocation_in_place() {
+    const SIZE: usize = 512;
+    let mut buf = AlignedBuf([0u8; SIZE]);
+    let node = unsafe { &mut *create_node(&mut buf.0[0] as *const _ as usize, SIZE) };
+
+    let mut allocator = LinkedListAllocator::new();
+    allocator.init(node);
+
+    unsafe {
+        let layout = Layout::from_size_align_unchecked(32, 8);
+        let ptr1 = allocator.alloc(layout);
+        let ptr2 = allocator.alloc(layout);
+        let ptr3 = allocator.alloc(layout);
+        ptr3.write_bytes(0xab, 32);
+
+        // ptr2 sits right behind ptr3
+        allocator.dealloc(ptr2, layout);
+
+        let grown = allocator.realloc(ptr3, layout, 64);
+        assert_eq!(grown, ptr3);
+        let data = std::slice::from_raw_parts(grown, 32);
+        assert!(data.iter().all(|b| *b == 0xab));
+
+        allocator.dealloc(grown, Layout::from_size_align_unchecked(64, 8));
+        allocator.dealloc(ptr1, layout);
+    }
+
+    let node = unsafe { &*(allocator.nodes_mut().unwrap()) };
+    assert!(node.free);
+    assert_eq!(node.size, SIZE - size_of::<LinkedAllocatorNode>());
+    assert_eq!(node.next, None);
+}
+
+#[test]
+fn should_shrink_allocation_in_place() {
+    const SIZE: usize = 512;
+    let mut buf = AlignedBuf([0u8; SIZE]);
+    let node = unsafe { &mut *create_node(&mut buf.0[0] as *const _ as usize, SIZE) };
+
+    let mut allocator = LinkedListAllocator::new();
+    allocator.init(node);
+
+    unsafe {
+        let layout = Layout::from_size_align_unchecked(256, 8);
+        let ptr = allocator.alloc(layout);
+        let free_before = free_bytes(&allocator);
+
+        let shrunk = allocator.realloc(ptr, layout, 64);
+        assert_eq!(shrunk, ptr);
+        assert_eq!(
+            free_bytes(&allocator),
+            free_before + 256 - 64 - size_of::<LinkedAllocatorNode>()
+        );
+
+        allocator.dealloc(shrunk, Layout::from_size_align_unchecked(64, 8));
+    }
+
+    let node = unsafe { &*(allocator.nodes_mut().unwrap()) };
+    assert!(node.free);
+    assert_eq!(node.size, SIZE - size_of::<LinkedAllocatorNode>());
+    assert_eq!(node.next, None);
+}
+
+#[test]
+fn should_move_allocation_when_it_cannot_grow_in_place() {
+    const SIZE: usize = 1024;
+    let mut buf = AlignedBuf([0u8; SIZE]);
+    let node = unsafe { &mut *create_node(&mut buf.0[0] as *const _ as usize, SIZE) };
+
+    let mut allocator = LinkedListAllocator::new();
+    allocator.init(node);
+
+    unsafe {
+        let layout = Layout::from_size_align_unchecked(32, 8);
+        let ptr1 = allocator.alloc(layout);
+        let ptr2 = allocator.alloc(layout);
+        ptr1.write_bytes(0xcd, 32);
+
+        // ptr1 is at the end of the heap
+        let moved = allocator.realloc(ptr1, layout, 128);
+        assert_ne!(moved, ptr1);
+        let data = std::slice::from_raw_parts(moved, 32);
+        assert!(data.iter().all(|b| *b == 0xcd));
+
+        allocator.dealloc(moved, Layout::from_size_align_unchecked(128, 8));
+        allocator.dealloc(ptr2, layout);
+    }
+
+    let node = unsafe { &*(allocator.nodes_mut().unwrap()) };
+    assert!(node.free);
+    assert_eq!(node.size, SIZE - size_of::<LinkedAllocatorNode>());
+    assert_eq!(node.next, None);
+}
+
+#[cfg(not(any(feature = "debug", feature = "track")))]
+#[test]
+fn should_report_heap_stats() {
+    const SIZE: usize = 512;
+    let mut buf = AlignedBuf([0u8; SIZE]);
+    let node = unsafe { &mut *create_node(&mut buf.0[0] as *const _ as usize, SIZE) };
+
+    let mut allocator = LinkedListAllocator::new();
+    allocator.init(node);
+
+    let stats = allocator.stats();
+    assert_eq!(stats.total, SIZE);
+    assert_eq!(stats.free, SIZE - size_of::<LinkedAllocatorNode>());
+    assert_eq!(stats.largest_free, stats.free);
+    assert_eq!(stats.allocations, 0);
+    assert_eq!(stats.fragmentation(), 0);
+
+    unsafe {
+        let layout = Layout::from_size_align_unchecked(32, 8);
+        let ptr1 = allocator.alloc(layout);
+        let ptr2 = allocator.alloc(layout);
+        let ptr3 = allocator.alloc(layout);
+        allocator.dealloc(ptr2, layout);
+
+        let stats = allocator.stats();
+        assert_eq!(stats.total, SIZE);
+        assert_eq!(stats.used, 64);
+        assert_eq!(stats.allocations, 2);
+        assert_eq!(stats.nodes, 4);
+        assert_eq!(
+            stats.free + stats.used + stats.nodes * size_of::<LinkedAllocatorNode>(),
+            SIZE
+        );
+        assert!(stats.largest_free < stats.free);
+        assert!(stats.fragmentation() > 0);
+
+        let nodes: Vec<NodeInfo> = allocator.nodes().collect();
+        assert_eq!(
+            nodes[1..],
+            [
+                NodeInfo {
+                    address: ptr3 as usize,
+                    size: 32,
+                    free: false
+                },
+                NodeInfo {
+                    address: ptr2 as usize,
+                    size: 32,
+                    free: true
+                },
+                NodeInfo {
+                    address: ptr1 as usize,
+                    size: 32,
+                    free: false
+                },
+            ]
+        );
+
+        allocator.dealloc(ptr1, layout);
+        allocator.dealloc(ptr3, layout);
+    }
+
+    assert_eq!(allocator.nodes().count(), 1);
+}
+
+#[test]
+fn should_allocate_from_disjoint_regions() {
+    const SIZE: usize = 256;
+    let mut buf1 = AlignedBuf([0u8; SIZE]);
+    let mut buf2 = AlignedBuf([0u8; 2 * SIZE]);
+    let node = unsafe { &mut *create_node(&mut buf1.0[0] as *const _ as usize, SIZE) };
+
+    let mut allocator = LinkedListAllocator::new();
+    allocator.init(node);
+
+    let region = &mut buf2.0[0] as *mut u8 as usize;
+    unsafe {
+        allocator.add_region(region, 2 * SIZE);
+
+        assert_eq!(allocator.stats().total, 3 * SIZE);
+        assert_eq!(allocator.stats().nodes, 2);
+
+        // Doesn't fit into the first region
+        let layout = Layout::from_size_align_unchecked(SIZE, 8);
+        let ptr = allocator.alloc(layout);
+        assert!((region..region + 2 * SIZE).contains(&(ptr as usize)));
+
+        // Freeing it must not merge the regions
+        allocator.dealloc(ptr, layout);
+        let nodes: Vec<NodeInfo> = allocator.nodes().collect();
+        assert_eq!(nodes.len(), 2);
+        assert!(nodes.iter().all(|node| node.free));
+        assert_eq!(allocator.stats().total, 3 * SIZE);
+    }
+}
+
+#[test]
+fn should_keep_regions_sorted_by_address() {
+    const SIZE: usize = 256;
+    let mut buf = AlignedBuf([0u8; 4 * SIZE]);
+    let start = &mut buf.0[0] as *mut u8 as usize;
+
+    // Regions can be added before init and in any order
+    let mut allocator = LinkedListAllocator::new();
+    unsafe {
+        allocator.add_region(start + 2 * SIZE, SIZE);
+        allocator.add_region(start, SIZE);
+    }
+
+    let addresses: Vec<usize> = allocator.nodes().map(|node| node.address).collect();
+    assert_eq!(
+        addresses,
+        [
+            start + size_of::<LinkedAllocatorNode>(),
+            start + 2 * SIZE + size_of::<LinkedAllocatorNode>()
+        ]
+    );
+
+    // Filling the gap merges everything into one node
+    unsafe { allocator.add_region(start + SIZE, SIZE) };
+    assert_eq!(allocator.stats().nodes, 1);
+    assert_eq!(allocator.stats().total, 3 * SIZE);
+
+    unsafe { allocator.add_region(start + 3 * SIZE, SIZE) };
+    assert_eq!(allocator.stats().nodes, 1);
+    assert_eq!(
+        allocator.stats().largest_free,
+        4 * SIZE - size_of::<LinkedAllocatorNode>()
+    );
+}
+
+#[test]
+#[should_panic(expected = "overlaps with the heap")]
+fn should_panic_on_overlapping_region() {
+    const SIZE: usize = 256;
+    let mut buf = AlignedBuf([0u8; SIZE]);
+    let start = &mut buf.0[0] as *mut u8 as usize;
+
+    let mut allocator = LinkedListAllocator::new();
+    unsafe {
+        allocator.add_region(start, SIZE);
+        allocator.add_region(start + SIZE / 2, SIZE / 2);
+    }
+}
+
+#[cfg(not(any(feature = "debug", feature = "track")))]
+#[test]
+fn should_merge_with_both_neighbours_through_boundary_tags() {
+    const SIZE: usize = 1024;
+    let mut buf = AlignedBuf([0u8; SIZE]);
+    let node = unsafe { &mut *create_node(&mut buf.0[0] as *const _ as usize, SIZE) };
+
+    let mut allocator = LinkedListAllocator::new();
+    allocator.init(node);
+
+    unsafe {
+        let layout = Layout::from_size_align_unchecked(64, 8);
+        let ptrs: Vec<*mut u8> = (0..6).map(|_| allocator.alloc(layout)).collect();
+
+        // Every other allocation is freed, none of them can merge yet
+        for ptr in ptrs.iter().step_by(2) {
+            allocator.dealloc(*ptr, layout);
+        }
+        assert_eq!(allocator.free_nodes().count(), 4);
+        assert_eq!(
+            allocator.free_nodes().count(),
+            allocator.nodes().filter(|n| n.free).count()
+        );
+
+        // Each of these sits between two free nodes
+        for ptr in ptrs.iter().skip(1).step_by(2) {
+            allocator.dealloc(*ptr, layout);
+        }
+    }
+
+    let free: Vec<NodeInfo> = allocator.free_nodes().collect();
+    assert_eq!(
+        free,
+        [NodeInfo {
+            address: &buf.0[0] as *const _ as usize + size_of::<LinkedAllocatorNode>(),
+            size: SIZE - size_of::<LinkedAllocatorNode>(),
+            free: true,
+        }]
+    );
+    assert_eq!(allocator.stats().nodes, 1);
+}
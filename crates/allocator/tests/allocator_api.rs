@@ -0,0 +1,75 @@
+// Needs nightly, run with `cargo +nightly test --features allocator-api`
+#![cfg(feature = "allocator-api")]
+#![feature(allocator_api)]
+
+use allocator::*;
+
+#[repr(C, align(64))]
+struct AlignedBuf<const N: usize>([u8; N]);
+
+#[test]
+fn should_allocate_collections_in_region() {
+    const SIZE: usize = 4096;
+    let mut buf = AlignedBuf([0u8; SIZE]);
+    let start = buf.0.as_mut_ptr() as usize;
+    let heap = unsafe { LockedLinkedListAllocator::from_region(start, SIZE) };
+
+    let mut vec: Vec<u64, _> = Vec::new_in(&heap);
+    vec.extend(0..100);
+    assert!((start..start + SIZE).contains(&(vec.as_ptr() as usize)));
+    assert_eq!(vec.iter().sum::<u64>(), 4950);
+
+    let boxed = Box::new_in([7u8; 16], &heap);
+    assert!((start..start + SIZE).contains(&(boxed.as_ptr() as usize)));
+    assert_eq!(heap.lock().stats().allocations, 2);
+
+    drop(vec);
+    drop(boxed);
+    assert_eq!(heap.lock().stats().allocations, 0);
+    assert_eq!(heap.lock().stats().nodes, 1);
+}
+
+#[test]
+fn should_keep_regions_independent() {
+    const SIZE: usize = 1024;
+    let mut buf1 = AlignedBuf([0u8; SIZE]);
+    let mut buf2 = AlignedBuf([0u8; SIZE]);
+    let heap1 =
+        unsafe { LockedLinkedListAllocator::from_region(buf1.0.as_mut_ptr() as usize, SIZE) };
+    let heap2 =
+        unsafe { LockedLinkedListAllocator::from_region(buf2.0.as_mut_ptr() as usize, SIZE) };
+
+    // Exhausting one arena doesn't affect the other one
+    let mut vec: Vec<u8, _> = Vec::new_in(&heap1);
+    assert!(vec.try_reserve_exact(2 * SIZE).is_err());
+    vec.try_reserve_exact(SIZE / 2).unwrap();
+    assert!(Vec::<u8, _>::new_in(&heap1)
+        .try_reserve_exact(SIZE / 2)
+        .is_err());
+
+    let mut other: Vec<u8, _> = Vec::new_in(&heap2);
+    other.try_reserve_exact(SIZE / 2).unwrap();
+    assert_eq!(heap1.lock().stats().allocations, 1);
+    assert_eq!(heap2.lock().stats().allocations, 1);
+}
+
+#[test]
+fn should_handle_zero_sized_and_realigned_allocations() {
+    const SIZE: usize = 2048;
+    let mut buf = AlignedBuf([0u8; SIZE]);
+    let heap = unsafe { LockedLinkedListAllocator::from_region(buf.0.as_mut_ptr() as usize, SIZE) };
+
+    let empty: Vec<u64, _> = Vec::with_capacity_in(0, &heap);
+    assert_eq!(empty.as_ptr() as usize % align_of::<u64>(), 0);
+    let unit: Box<(), _> = Box::new_in((), &heap);
+    drop(unit);
+    assert_eq!(heap.lock().stats().allocations, 0);
+
+    let mut vec: Vec<u32, _> = Vec::new_in(&heap);
+    for i in 0..200 {
+        vec.push(i);
+    }
+    vec.shrink_to_fit();
+    assert_eq!(vec.len(), 200);
+    assert!(vec.iter().copied().eq(0..200));
+}
@@ -0,0 +1,191 @@
+#![cfg(feature = "debug")]
+
+use allocator::*;
+use core::alloc::{GlobalAlloc, Layout};
+
+#[repr(C, align(64))]
+struct AlignedBuf<const N: usize>([u8; N]);
+
+fn create_allocator(buf: &mut [u8]) -> LinkedListAllocator {
+    let node = unsafe { &mut *(buf.as_mut_ptr() as *mut LinkedAllocatorNode) };
+    *node = LinkedAllocatorNode::new(buf.len());
+
+    let mut allocator = LinkedListAllocator::new();
+    allocator.init(node);
+    allocator
+}
+
+#[test]
+fn should_surround_allocations_with_canaries() {
+    let mut buf = AlignedBuf([0u8; 1024]);
+    let allocator = create_allocator(&mut buf.0);
+
+    unsafe {
+        let layout = Layout::from_size_align(40, 8).unwrap();
+        let ptr = allocator.alloc(layout);
+        assert_eq!(ptr as usize % 8, 0);
+        assert_eq!(*ptr.sub(1), CANARY);
+        assert_eq!(*ptr.add(40), CANARY);
+        assert_eq!(*ptr.add(40 + CANARY_SIZE - 1), CANARY);
+        assert_eq!(allocator.check(ptr, layout), Ok(()));
+
+        allocator.dealloc(ptr, layout);
+    }
+}
+
+#[test]
+fn should_poison_freed_memory() {
+    let mut buf = AlignedBuf([0u8; 1024]);
+    let allocator = create_allocator(&mut buf.0);
+
+    unsafe {
+        let layout = Layout::from_size_align(64, 16).unwrap();
+        let ptr1 = allocator.alloc(layout);
+        // Keeps the node of ptr1 from being merged into the start node
+        let ptr2 = allocator.alloc(layout);
+        ptr1.write_bytes(0x11, 64);
+
+        allocator.dealloc(ptr1, layout);
+        let data = std::slice::from_raw_parts(ptr1, 64);
+        assert!(data.iter().all(|b| *b == POISON));
+
+        allocator.dealloc(ptr2, layout);
+    }
+}
+
+#[test]
+fn should_detect_double_free() {
+    let mut buf = AlignedBuf([0u8; 1024]);
+    let allocator = create_allocator(&mut buf.0);
+
+    unsafe {
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let ptr = allocator.alloc(layout);
+        allocator.dealloc(ptr, layout);
+
+        assert_eq!(
+            allocator.check(ptr, layout),
+            Err(HeapError::DoubleFree { ptr: ptr as usize })
+        );
+    }
+}
+
+#[test]
+fn should_detect_layout_mismatch() {
+    let mut buf = AlignedBuf([0u8; 1024]);
+    let allocator = create_allocator(&mut buf.0);
+
+    unsafe {
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let wrong = Layout::from_size_align(48, 8).unwrap();
+        let ptr = allocator.alloc(layout);
+
+        assert_eq!(
+            allocator.check(ptr, wrong),
+            Err(HeapError::LayoutMismatch {
+                ptr: ptr as usize,
+                allocated: layout,
+                freed: wrong
+            })
+        );
+
+        allocator.dealloc(ptr, layout);
+    }
+}
+
+#[test]
+fn should_detect_overwritten_canary() {
+    let mut buf = AlignedBuf([0u8; 1024]);
+    let allocator = create_allocator(&mut buf.0);
+
+    unsafe {
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let ptr = allocator.alloc(layout);
+        *ptr.add(33) = 0;
+
+        assert_eq!(
+            allocator.check(ptr, layout),
+            Err(HeapError::CorruptedCanary {
+                ptr: ptr as usize,
+                address: ptr as usize + 33
+            })
+        );
+    }
+}
+
+#[test]
+fn should_detect_invalid_pointer() {
+    let mut buf = AlignedBuf([0u8; 1024]);
+    let allocator = create_allocator(&mut buf.0);
+
+    unsafe {
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let ptr = allocator.alloc(layout);
+
+        assert_eq!(
+            allocator.check(ptr.add(8), layout),
+            Err(HeapError::InvalidPointer {
+                ptr: ptr as usize + 8
+            })
+        );
+
+        // Its header would be in front of the node
+        let start = allocator.nodes().find(|n| !n.free).unwrap().address as *mut u8;
+        assert_eq!(
+            allocator.check(start, layout),
+            Err(HeapError::InvalidPointer {
+                ptr: start as usize
+            })
+        );
+
+        // Outside of the heap
+        let outside = buf.0.as_mut_ptr().wrapping_sub(64);
+        assert_eq!(
+            allocator.check(outside, layout),
+            Err(HeapError::InvalidPointer {
+                ptr: outside as usize
+            })
+        );
+
+        allocator.dealloc(ptr, layout);
+    }
+}
+
+#[test]
+#[should_panic(expected = "double free")]
+fn should_panic_on_double_free() {
+    let mut buf = AlignedBuf([0u8; 1024]);
+    let allocator = create_allocator(&mut buf.0);
+
+    unsafe {
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let ptr = allocator.alloc(layout);
+        allocator.dealloc(ptr, layout);
+        allocator.dealloc(ptr, layout);
+    }
+}
+
+#[test]
+fn should_keep_canaries_after_realloc() {
+    let mut buf = AlignedBuf([0u8; 1024]);
+    let allocator = create_allocator(&mut buf.0);
+
+    unsafe {
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let ptr = allocator.alloc(layout);
+        ptr.write_bytes(0x42, 32);
+
+        let ptr = allocator.realloc(ptr, layout, 128);
+        let layout = Layout::from_size_align(128, 8).unwrap();
+        assert_eq!(allocator.check(ptr, layout), Ok(()));
+        assert!(std::slice::from_raw_parts(ptr, 32)
+            .iter()
+            .all(|b| *b == 0x42));
+
+        let ptr = allocator.realloc(ptr, layout, 16);
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        assert_eq!(allocator.check(ptr, layout), Ok(()));
+
+        allocator.dealloc(ptr, layout);
+    }
+}
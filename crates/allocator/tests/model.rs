@@ -0,0 +1,464 @@
+// Runs random sequences of alloc/dealloc/realloc against the allocators and checks them against a
+// model of the live allocations after every step. Failures print the seed and step, set
+// MODEL_SEED to replay a single seed.
+
+use allocator::*;
+use core::alloc::{GlobalAlloc, Layout};
+use std::ops::Range;
+
+const HEAP_SIZE: usize = 64 * 1024;
+const SEEDS: u64 = 16;
+const STEPS: usize = 1000;
+
+#[repr(C, align(4096))]
+struct AlignedBuf([u8; HEAP_SIZE]);
+
+// xorshift64*, good enough to shuffle allocation patterns around
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn layout(&mut self) -> Layout {
+        let size = match self.below(10) {
+            0..=5 => 1 + self.below(64),
+            6..=8 => 1 + self.below(512),
+            _ => 1 + self.below(4096),
+        };
+        let align = match self.below(20) {
+            0 => 4096,
+            1..=2 => 1 << (5 + self.below(3)),
+            _ => 1 << self.below(5),
+        };
+        Layout::from_size_align(size, align).unwrap()
+    }
+}
+
+struct Allocation {
+    ptr: *mut u8,
+    layout: Layout,
+    fill: u8,
+}
+
+trait Backend: GlobalAlloc + Sized {
+    fn over_region(heap: Range<usize>) -> Self;
+    fn check_invariants(&self, heap: &Range<usize>, live: &[Allocation]);
+    // Describes the free memory, it has to match the fresh allocator once everything is freed
+    fn fingerprint(&self) -> Vec<usize>;
+}
+
+impl Backend for LinkedListAllocator {
+    fn over_region(heap: Range<usize>) -> Self {
+        let node = unsafe { &mut *(heap.start as *mut LinkedAllocatorNode) };
+        *node = LinkedAllocatorNode::new(heap.len());
+
+        let mut allocator = LinkedListAllocator::new();
+        allocator.init(node);
+        allocator
+    }
+
+    fn check_invariants(&self, heap: &Range<usize>, live: &[Allocation]) {
+        let header = size_of::<LinkedAllocatorNode>();
+        let nodes: Vec<NodeInfo> = self.nodes().collect();
+
+        // Nodes tile the heap without gaps, in address order
+        let mut expected_start = heap.start;
+        for node in &nodes {
+            assert_eq!(node.address - header, expected_start, "gap before {node:?}");
+            assert!(node.size > 0, "empty node {node:?}");
+            expected_start = node.address + node.size;
+        }
+        assert_eq!(expected_start, heap.end, "nodes don't cover the heap");
+
+        // Every live allocation lives in a used node
+        for allocation in live {
+            let start = allocation.ptr as usize;
+            assert!(
+                nodes.iter().any(|n| !n.free
+                    && n.address <= start
+                    && start + allocation.layout.size() <= n.address + n.size),
+                "0x{start:x} {:?} is not in a used node",
+                allocation.layout
+            );
+        }
+
+        let stats = self.stats();
+        assert_eq!(stats.total, heap.len());
+        assert_eq!(stats.used + stats.free + stats.nodes * header, heap.len());
+        check_boundary_tags(self);
+    }
+
+    fn fingerprint(&self) -> Vec<usize> {
+        self.nodes()
+            .flat_map(|n| [n.address, n.size, n.free as usize])
+            .collect()
+    }
+}
+
+impl Backend for BuddyAllocator {
+    fn over_region(heap: Range<usize>) -> Self {
+        let mut allocator = BuddyAllocator::new();
+        unsafe { allocator.init(heap.start, heap.len()) };
+        allocator
+    }
+
+    fn check_invariants(&self, heap: &Range<usize>, live: &[Allocation]) {
+        let mut free: Vec<(usize, usize)> = (0..ORDERS)
+            .flat_map(|order| self.free_list(order).map(move |block| (block, order)))
+            .collect();
+        free.sort();
+
+        for &(block, order) in &free {
+            let size = block_size(order);
+            assert_eq!(
+                block % size,
+                0,
+                "0x{block:x} isn't aligned to order {order}"
+            );
+            assert!(
+                heap.start <= block && block + size <= heap.end,
+                "0x{block:x} of order {order} is outside of the heap"
+            );
+            // Free buddies are always merged
+            assert!(
+                free.binary_search(&(block ^ size, order)).is_err(),
+                "0x{block:x} is free together with its buddy (order {order})"
+            );
+        }
+
+        // A block that is free together with its parent (or any bigger block containing it)
+        // overlaps it
+        for pair in free.windows(2) {
+            let ((a, a_order), (b, b_order)) = (pair[0], pair[1]);
+            assert!(
+                a + block_size(a_order) <= b,
+                "0x{a:x} of order {a_order} overlaps 0x{b:x} of order {b_order}"
+            );
+        }
+
+        for allocation in live {
+            let start = allocation.ptr as usize;
+            let end = start + allocation.layout.size();
+            assert!(
+                !free
+                    .iter()
+                    .any(|&(block, order)| block < end && start < block + block_size(order)),
+                "0x{start:x} {:?} overlaps a free block",
+                allocation.layout
+            );
+        }
+    }
+
+    fn fingerprint(&self) -> Vec<usize> {
+        (0..ORDERS).map(|o| self.free_blocks(o)).collect()
+    }
+}
+
+impl Backend for SlabAllocator {
+    fn over_region(heap: Range<usize>) -> Self {
+        let node = unsafe { &mut *(heap.start as *mut LinkedAllocatorNode) };
+        *node = LinkedAllocatorNode::new(heap.len());
+
+        let mut allocator = SlabAllocator::new();
+        allocator.init(node);
+        allocator
+    }
+
+    fn check_invariants(&self, heap: &Range<usize>, live: &[Allocation]) {
+        let stats = self.stats();
+        assert_eq!(stats.total, heap.len());
+
+        let slabs: Vec<NodeInfo> = self.nodes().filter(|n| !n.free).collect();
+        let mut free: Vec<(usize, usize)> = (0..SIZE_CLASSES.len())
+            .flat_map(|class| {
+                self.free_list(class)
+                    .map(move |block| (block, SIZE_CLASSES[class]))
+            })
+            .collect();
+        free.sort();
+
+        // Free blocks live in slabs, are on one list only and don't overlap each other
+        for &(block, size) in &free {
+            assert!(
+                slabs
+                    .iter()
+                    .any(|n| n.address <= block && block + size <= n.address + n.size),
+                "free block 0x{block:x} ({size} bytes) is not in a slab"
+            );
+        }
+        for pair in free.windows(2) {
+            let ((a, a_size), (b, _)) = (pair[0], pair[1]);
+            assert!(a + a_size <= b, "free blocks 0x{a:x} and 0x{b:x} overlap");
+        }
+
+        // Live allocations are never on a free list
+        for allocation in live {
+            let start = allocation.ptr as usize;
+            let end = start + allocation.layout.size();
+            assert!(
+                !free
+                    .iter()
+                    .any(|&(block, size)| block < end && start < block + size),
+                "0x{start:x} {:?} is on a free list",
+                allocation.layout
+            );
+        }
+    }
+
+    // Slabs stay with their size class, so the heap never looks fresh again
+    fn fingerprint(&self) -> Vec<usize> {
+        Vec::new()
+    }
+}
+
+// The heap split into regions with holes between them, added in reverse order
+struct Regions {
+    allocator: LinkedListAllocator,
+    regions: Vec<Range<usize>>,
+}
+
+unsafe impl GlobalAlloc for Regions {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.allocator.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.allocator.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.allocator.realloc(ptr, layout, new_size)
+    }
+}
+
+impl Backend for Regions {
+    fn over_region(heap: Range<usize>) -> Self {
+        let quarter = heap.len() / 4 / 8 * 8;
+        let regions = vec![
+            heap.start..heap.start + quarter,
+            heap.start + quarter + 64..heap.start + 2 * quarter,
+            heap.start + 3 * quarter..heap.end,
+        ];
+
+        let mut allocator = LinkedListAllocator::new();
+        for region in regions.iter().rev() {
+            unsafe { allocator.add_region(region.start, region.len()) };
+        }
+        Regions { allocator, regions }
+    }
+
+    fn check_invariants(&self, _heap: &Range<usize>, live: &[Allocation]) {
+        let header = size_of::<LinkedAllocatorNode>();
+        let nodes: Vec<NodeInfo> = self.allocator.nodes().collect();
+
+        // Nodes tile the regions and never span a hole
+        let mut nodes_iter = nodes.iter().peekable();
+        for region in &self.regions {
+            let mut expected_start = region.start;
+            while let Some(node) = nodes_iter.next_if(|n| n.address - header < region.end) {
+                assert_eq!(node.address - header, expected_start, "gap before {node:?}");
+                assert!(node.size > 0, "empty node {node:?}");
+                expected_start = node.address + node.size;
+            }
+            assert_eq!(expected_start, region.end, "nodes don't cover {region:x?}");
+        }
+        assert!(nodes_iter.next().is_none(), "nodes outside of the regions");
+
+        for allocation in live {
+            let start = allocation.ptr as usize;
+            assert!(
+                nodes.iter().any(|n| !n.free
+                    && n.address <= start
+                    && start + allocation.layout.size() <= n.address + n.size),
+                "0x{start:x} {:?} is not in a used node",
+                allocation.layout
+            );
+        }
+
+        let total: usize = self.regions.iter().map(|r| r.len()).sum();
+        assert_eq!(self.allocator.stats().total, total);
+        check_boundary_tags(&self.allocator);
+    }
+
+    fn fingerprint(&self) -> Vec<usize> {
+        self.allocator.fingerprint()
+    }
+}
+
+// The free list holds exactly the free nodes and the boundary tags agree with the node list
+fn check_boundary_tags(allocator: &LinkedListAllocator) {
+    let mut free: Vec<NodeInfo> = allocator.nodes().filter(|n| n.free).collect();
+    let mut free_list: Vec<NodeInfo> = allocator.free_nodes().collect();
+    free.sort_by_key(|n| n.address);
+    free_list.sort_by_key(|n| n.address);
+    assert_eq!(free, free_list, "free list is out of sync");
+
+    let Some(first) = (unsafe { allocator.nodes_mut() }) else {
+        return;
+    };
+    for node in unsafe { (*first).as_iter() } {
+        if node.free {
+            let footer = (node.end_address() + 1 - size_of::<usize>()) as *const usize;
+            assert_eq!(
+                unsafe { footer.read_unaligned() },
+                node.size,
+                "bad footer {node:?}"
+            );
+        }
+        if let Some(next) = node.physical_next() {
+            assert_eq!(next.prev_free, node.free, "bad prev_free behind {node:?}");
+        }
+    }
+}
+
+fn check_allocations(heap: &Range<usize>, live: &[Allocation]) {
+    let mut ranges: Vec<Range<usize>> = live
+        .iter()
+        .map(|a| a.ptr as usize..a.ptr as usize + a.layout.size())
+        .collect();
+    ranges.sort_by_key(|r| r.start);
+
+    for pair in ranges.windows(2) {
+        assert!(
+            pair[0].end <= pair[1].start,
+            "{:x?} overlaps {:x?}",
+            pair[0],
+            pair[1]
+        );
+    }
+    for range in &ranges {
+        assert!(
+            heap.start <= range.start && range.end <= heap.end,
+            "{range:x?} outside of heap"
+        );
+    }
+
+    for allocation in live {
+        assert_eq!(allocation.ptr as usize % allocation.layout.align(), 0);
+        let data = unsafe { std::slice::from_raw_parts(allocation.ptr, allocation.layout.size()) };
+        assert!(
+            data.iter().all(|b| *b == allocation.fill),
+            "contents of 0x{:x} {:?} changed",
+            allocation.ptr as usize,
+            allocation.layout
+        );
+    }
+}
+
+fn run<B: Backend>(seed: u64) {
+    let mut buf = Box::new(AlignedBuf([0u8; HEAP_SIZE]));
+    // Don't always start on a page boundary
+    let offset = (seed as usize % 8) * 8;
+    let heap = buf.0.as_mut_ptr() as usize + offset..buf.0.as_mut_ptr() as usize + HEAP_SIZE;
+
+    let allocator = B::over_region(heap.clone());
+    let fresh = allocator.fingerprint();
+    let mut rng = Rng(seed.wrapping_mul(0x9e3779b97f4a7c15) | 1);
+    let mut live: Vec<Allocation> = Vec::new();
+
+    for step in 0..STEPS {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            match rng.below(10) {
+                0..=4 => {
+                    let layout = rng.layout();
+                    let ptr = unsafe { allocator.alloc(layout) };
+                    if !ptr.is_null() {
+                        let fill = rng.next() as u8;
+                        unsafe { ptr.write_bytes(fill, layout.size()) };
+                        live.push(Allocation { ptr, layout, fill });
+                    }
+                }
+                5..=7 if !live.is_empty() => {
+                    let allocation = live.swap_remove(rng.below(live.len()));
+                    unsafe { allocator.dealloc(allocation.ptr, allocation.layout) };
+                }
+                8..=9 if !live.is_empty() => {
+                    let i = rng.below(live.len());
+                    let old = &live[i];
+                    let new_size = rng.layout().size();
+                    let ptr = unsafe { allocator.realloc(old.ptr, old.layout, new_size) };
+                    if !ptr.is_null() {
+                        let layout = Layout::from_size_align(new_size, old.layout.align()).unwrap();
+                        let data = unsafe {
+                            std::slice::from_raw_parts(ptr, old.layout.size().min(new_size))
+                        };
+                        assert!(
+                            data.iter().all(|b| *b == old.fill),
+                            "realloc lost the contents"
+                        );
+
+                        unsafe { ptr.write_bytes(old.fill, new_size) };
+                        live[i] = Allocation {
+                            ptr,
+                            layout,
+                            fill: old.fill,
+                        };
+                    }
+                }
+                _ => {}
+            }
+
+            check_allocations(&heap, &live);
+            allocator.check_invariants(&heap, &live);
+        }));
+
+        if let Err(err) = result {
+            eprintln!("model check failed with seed {seed} at step {step}");
+            std::panic::resume_unwind(err);
+        }
+    }
+
+    for allocation in live.drain(..) {
+        unsafe { allocator.dealloc(allocation.ptr, allocation.layout) };
+    }
+    allocator.check_invariants(&heap, &live);
+    assert_eq!(
+        allocator.fingerprint(),
+        fresh,
+        "memory wasn't recovered (seed {seed})"
+    );
+}
+
+fn seeds() -> Vec<u64> {
+    match std::env::var("MODEL_SEED") {
+        Ok(seed) => vec![seed.parse().expect("MODEL_SEED has to be a number")],
+        Err(_) => (0..SEEDS).collect(),
+    }
+}
+
+#[test]
+fn linked_list_matches_model() {
+    for seed in seeds() {
+        run::<LinkedListAllocator>(seed);
+    }
+}
+
+#[test]
+fn buddy_matches_model() {
+    for seed in seeds() {
+        run::<BuddyAllocator>(seed);
+    }
+}
+
+#[test]
+fn slab_matches_model() {
+    for seed in seeds() {
+        run::<SlabAllocator>(seed);
+    }
+}
+
+#[test]
+fn regions_match_model() {
+    for seed in seeds() {
+        run::<Regions>(seed);
+    }
+}
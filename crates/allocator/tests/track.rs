@@ -0,0 +1,133 @@
+// Addresses and sizes of tracked allocations include the canaries in debug mode
+#![cfg(all(feature = "track", not(feature = "debug")))]
+
+use allocator::*;
+use core::alloc::{GlobalAlloc, Layout};
+use std::cell::Cell;
+
+#[repr(C, align(64))]
+struct AlignedBuf<const N: usize>([u8; N]);
+
+thread_local! {
+    static CALLER: Cell<usize> = const { Cell::new(0) };
+    static CLOCK: Cell<u64> = const { Cell::new(0) };
+}
+
+// Pretends every allocation of the current thread comes from CALLER, the clock ticks once per
+// allocation
+fn track() -> CallSite {
+    let caller = CALLER.get();
+    CLOCK.set(CLOCK.get() + 1);
+    CallSite {
+        caller,
+        backtrace: [caller + 1, caller + 2, 0, 0],
+        timestamp: CLOCK.get(),
+    }
+}
+
+fn create_allocator(buf: &mut [u8]) -> LinkedListAllocator {
+    let node = unsafe { &mut *(buf.as_mut_ptr() as *mut LinkedAllocatorNode) };
+    *node = LinkedAllocatorNode::new(buf.len());
+
+    let mut allocator = LinkedListAllocator::new();
+    allocator.init(node);
+    allocator.set_track_handler(track);
+    allocator
+}
+
+unsafe fn alloc_from(allocator: &LinkedListAllocator, caller: usize, size: usize) -> *mut u8 {
+    CALLER.set(caller);
+    allocator.alloc(Layout::from_size_align_unchecked(size, 8))
+}
+
+#[test]
+fn should_record_call_site_of_allocations() {
+    let mut buf = AlignedBuf([0u8; 2048]);
+    let allocator = create_allocator(&mut buf.0);
+
+    unsafe {
+        let ptr = alloc_from(&allocator, 0x1000, 64);
+
+        let tracked: Vec<TrackedAllocation> = allocator.tracked().collect();
+        assert_eq!(tracked.len(), 1);
+        assert_eq!(tracked[0].address, ptr as usize);
+        assert_eq!(tracked[0].size, 64);
+        assert_eq!(tracked[0].site.caller, 0x1000);
+        assert_eq!(tracked[0].site.backtrace, [0x1001, 0x1002, 0, 0]);
+
+        allocator.dealloc(ptr, Layout::from_size_align_unchecked(64, 8));
+    }
+
+    assert_eq!(allocator.tracked().count(), 0);
+}
+
+#[test]
+fn should_group_leaks_by_call_site() {
+    let mut buf = AlignedBuf([0u8; 4096]);
+    let allocator = create_allocator(&mut buf.0);
+
+    unsafe {
+        let first = alloc_from(&allocator, 0x1000, 32);
+        alloc_from(&allocator, 0x2000, 128);
+        alloc_from(&allocator, 0x1000, 64);
+        let freed = alloc_from(&allocator, 0x3000, 16);
+        allocator.dealloc(freed, Layout::from_size_align_unchecked(16, 8));
+
+        let mut reports = [CallSiteReport::default(); 4];
+        let (report, skipped) = allocator.leak_report(&mut reports);
+        assert_eq!(report.len(), 2);
+        assert_eq!(skipped, 0);
+
+        assert_eq!(report[0].caller, 0x1000);
+        assert_eq!(report[0].allocations, 2);
+        assert_eq!(report[0].bytes, 96);
+        let oldest = allocator
+            .tracked()
+            .find(|a| a.address == first as usize)
+            .unwrap();
+        assert_eq!(report[0].oldest, oldest.site);
+
+        assert_eq!(report[1].caller, 0x2000);
+        assert_eq!(report[1].allocations, 1);
+        assert_eq!(report[1].bytes, 128);
+
+        // Callers that don't fit are counted instead
+        let mut reports = [CallSiteReport::default(); 1];
+        let (report, skipped) = allocator.leak_report(&mut reports);
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].allocations + skipped, 3);
+    }
+}
+
+#[test]
+fn should_keep_call_site_when_resizing_in_place() {
+    let mut buf = AlignedBuf([0u8; 2048]);
+    let allocator = create_allocator(&mut buf.0);
+
+    unsafe {
+        let layout = Layout::from_size_align_unchecked(256, 8);
+        let ptr = alloc_from(&allocator, 0x1000, 256);
+
+        CALLER.set(0x2000);
+        let shrunk = allocator.realloc(ptr, layout, 64);
+        assert_eq!(shrunk, ptr);
+
+        let tracked: Vec<TrackedAllocation> = allocator.tracked().collect();
+        assert_eq!(tracked.len(), 1);
+        assert_eq!(tracked[0].site.caller, 0x1000);
+    }
+}
+
+#[test]
+fn should_not_track_without_handler() {
+    let mut buf = AlignedBuf([0u8; 1024]);
+    let node = unsafe { &mut *(buf.0.as_mut_ptr() as *mut LinkedAllocatorNode) };
+    *node = LinkedAllocatorNode::new(buf.0.len());
+    let mut allocator = LinkedListAllocator::new();
+    allocator.init(node);
+
+    unsafe { alloc_from(&allocator, 0x1000, 32) };
+
+    let tracked: Vec<TrackedAllocation> = allocator.tracked().collect();
+    assert_eq!(tracked[0].site, CallSite::default());
+}
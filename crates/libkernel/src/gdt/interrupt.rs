@@ -1,5 +1,8 @@
+use x86_64::registers::control::Cr2;
 use x86_64::structures::idt::{InterruptStackFrame, PageFaultErrorCode};
 
+use crate::memory::paging::{dump, mapper::ActivePageTable};
+use crate::memory::region::Region;
 use crate::println;
 
 pub(crate) extern "x86-interrupt" fn breakpoint(stack_frame: InterruptStackFrame) {
@@ -10,7 +13,48 @@ pub(crate) extern "x86-interrupt" fn page_fault(
     stack_frame: InterruptStackFrame,
     err_code: PageFaultErrorCode,
 ) {
-    panic!("EXCEPTION: PAGE FAULT\n{:#?}\n{:#?}", stack_frame, err_code);
+    let address = Cr2::read().as_u64();
+
+    println!(
+        "EXCEPTION: PAGE FAULT at {:#x} ({})",
+        address,
+        Region(address)
+    );
+    print_page_fault_cause(err_code);
+
+    // The faulting code can hold the memory manager, the tables are only read
+    let active_table = unsafe { ActivePageTable::new() };
+    dump::print_walk(&active_table, address);
+
+    panic!("PAGE FAULT at {:#x}\n{:#?}", address, stack_frame);
+}
+
+fn print_page_fault_cause(err_code: PageFaultErrorCode) {
+    let access = if err_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        "Instruction fetch"
+    } else if err_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+        "Write"
+    } else {
+        "Read"
+    };
+    let mode = if err_code.contains(PageFaultErrorCode::USER_MODE) {
+        "user"
+    } else {
+        "kernel"
+    };
+    let cause = if err_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        "protection violation"
+    } else {
+        "page not present"
+    };
+    println!("{} in {} mode, {}", access, mode, cause);
+
+    if err_code.contains(PageFaultErrorCode::MALFORMED_TABLE) {
+        println!("A reserved bit is set in a page table entry");
+    }
+    if err_code.contains(PageFaultErrorCode::PROTECTION_KEY) {
+        println!("Protection key violation");
+    }
 }
 
 pub(crate) extern "x86-interrupt" fn double_fault(
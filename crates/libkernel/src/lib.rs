@@ -1,18 +1,22 @@
 #![no_std]
 #![no_main]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![feature(let_chains)]
 
 extern crate alloc;
 
 use alloc::string::String;
 use multiboot2::{BootInformation, BootInformationHeader};
+use spin::Once;
 
 mod gdt;
 mod memory;
 mod serial;
 mod vga;
 
+static BOOT_INFO: Once<BootInformation<'static>> = Once::new();
+
 #[panic_handler]
 fn panic_handler(info: &core::panic::PanicInfo) -> ! {
     println!("Kernel panic: {info}");
@@ -26,11 +30,12 @@ pub extern "C" fn rust_main(multiboot_info_addr: usize) {
 
     println!("Starting VOS...");
 
-    let boot_info =
+    let boot_info = BOOT_INFO.call_once(|| {
         unsafe { BootInformation::load(multiboot_info_addr as *const BootInformationHeader) }
-            .expect("Error while parsing multiboot header: ");
+            .expect("Error while parsing multiboot header: ")
+    });
 
-    init(&boot_info);
+    init(boot_info);
 
     let str = String::from("Hello world on heap!");
     println!("{}", str);
@@ -38,7 +43,7 @@ pub extern "C" fn rust_main(multiboot_info_addr: usize) {
     loop {}
 }
 
-fn init(boot_info: &BootInformation) -> () {
+fn init(boot_info: &'static BootInformation) -> () {
     gdt::init_gdt();
     gdt::init_idt();
     memory::init(boot_info);
@@ -0,0 +1,75 @@
+use spin::Once;
+
+use crate::memory::frames::{PhysicalFrame, PAGE_SIZE};
+use crate::memory::manager::MemoryManager;
+use crate::memory::map::{AreaKind, MemoryMap, MemoryMapArea};
+use crate::memory::paging::entry::EntryFlags;
+use crate::memory::paging::PageRange;
+use crate::memory::{PhysicalAddress, VirtualAddress};
+
+// First P4 entry of the higher half (index 256), the heap is in entry 510 about 127 TiB above it
+pub const PHYSICAL_MEMORY_OFFSET: VirtualAddress = 0xffff_8000_0000_0000;
+// Everything up to the heap
+pub const MAX_PHYSICAL_MEMORY: u64 = 0xffff_ff00_0000_0000 - PHYSICAL_MEMORY_OFFSET;
+
+// The memory map the direct map was made from, None until it exists
+static MAPPED: Once<&'static MemoryMap> = Once::new();
+
+// Maps the areas of the memory map, holes between them stay unmapped so nothing can access memory
+// that might not exist through the direct map. Huge pages are used wherever possible, so it only
+// takes a few frames for page tables.
+pub(super) fn init(manager: &mut MemoryManager, map: &'static MemoryMap) {
+    assert!(MAPPED.get().is_none(), "Direct map exists");
+
+    // Areas can share a page at their ends, it is mapped with the first one
+    let mut mapped_end = 0;
+    for area in map.areas() {
+        let (start, end) = pages_of(area);
+        let start = start.max(mapped_end);
+        if start >= end {
+            continue;
+        }
+        assert!(
+            end <= MAX_PHYSICAL_MEMORY,
+            "Physical memory doesn't fit in the direct map"
+        );
+
+        // Only RAM may be cached, the rest can be device memory
+        let flags = match area.kind {
+            AreaKind::Available | AreaKind::AcpiReclaimable | AreaKind::Nvs => {
+                EntryFlags::WRITABLE | EntryFlags::NOEXECUTE
+            }
+            _ => EntryFlags::WRITABLE | EntryFlags::NOEXECUTE | EntryFlags::NO_CACHE,
+        };
+        manager
+            .map_range_to(
+                PageRange::containing(PHYSICAL_MEMORY_OFFSET + start, PHYSICAL_MEMORY_OFFSET + end),
+                PhysicalFrame::by_addr(start),
+                flags,
+            )
+            .expect("Cannot map physical memory");
+        mapped_end = end;
+    }
+    MAPPED.call_once(|| map);
+}
+
+// Where `address` can be accessed, None if it's outside of the direct map or the direct map doesn't
+// exist yet
+pub fn phys_to_virt(address: PhysicalAddress) -> Option<VirtualAddress> {
+    let map = MAPPED.get()?;
+    map.areas()
+        .iter()
+        .any(|area| {
+            let (start, end) = pages_of(area);
+            (start..end).contains(&address)
+        })
+        .then(|| PHYSICAL_MEMORY_OFFSET + address)
+}
+
+// The pages an area touches
+fn pages_of(area: &MemoryMapArea) -> (PhysicalAddress, PhysicalAddress) {
+    (
+        area.start / PAGE_SIZE * PAGE_SIZE,
+        area.end.div_ceil(PAGE_SIZE) * PAGE_SIZE,
+    )
+}
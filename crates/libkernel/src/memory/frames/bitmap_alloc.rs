@@ -0,0 +1,178 @@
+use core::ops::RangeInclusive;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use multiboot2::{MemoryArea, MemoryAreaType};
+
+use super::{
+    bump_alloc::BumpAllocator, FrameAlloc, FrameStats, PhysicalFrame, Zone, ZoneInfo, PAGE_SIZE,
+    ZONES,
+};
+use crate::memory::PhysicalAddress;
+
+// One bit per frame up to the end of the highest available memory area, the bitmaps live on the
+// heap so this can only be used once the heap exists. It only hands out single frames, see the buddy
+// allocator for contiguous ones.
+pub struct BitmapFrameAllocator {
+    // Set bits are frames that are used or not available
+    bitmap: Vec<u64>,
+    // Set bits are frames inside of an available memory area, only those can ever be freed
+    available: Vec<u64>,
+    // Every frame in front of this one is used, allocation starts looking here
+    next_free: u64,
+    // Indexed by zone
+    frames: [u64; ZONES.len()],
+    free: [u64; ZONES.len()],
+    // Frames that were never handed out because the kernel or the bootloader uses them
+    reserved: u64,
+}
+
+impl BitmapFrameAllocator {
+    pub const NAME: &'static str = "bitmap";
+
+    pub fn new(areas: &[MemoryArea]) -> Self {
+        let available = || {
+            areas
+                .iter()
+                .filter(|a| a.typ() == MemoryAreaType::Available)
+        };
+        let frames = available()
+            .map(|a| a.end_address() / PAGE_SIZE)
+            .max()
+            .unwrap_or(0);
+
+        let mut allocator = BitmapFrameAllocator {
+            bitmap: vec![u64::MAX; frames.div_ceil(64) as usize],
+            available: vec![0; frames.div_ceil(64) as usize],
+            next_free: 0,
+            frames: [0; ZONES.len()],
+            free: [0; ZONES.len()],
+            reserved: 0,
+        };
+
+        // Only frames that are completely inside of an available area can be handed out
+        for area in available() {
+            let start = area.start_address().div_ceil(PAGE_SIZE);
+            let end = area.end_address() / PAGE_SIZE;
+            for number in start..end {
+                allocator.available[(number / 64) as usize] |= 1 << (number % 64);
+                allocator.set_used(number, false);
+            }
+        }
+        allocator.frames = allocator.free;
+        allocator
+    }
+
+    // Marks every frame that overlaps `range` as used
+    pub fn reserve(&mut self, range: RangeInclusive<PhysicalAddress>) {
+        for number in range.start() / PAGE_SIZE..=range.end() / PAGE_SIZE {
+            if self.is_available(number) && !self.is_used(number) {
+                self.set_used(number, true);
+                self.reserved += 1;
+            }
+        }
+    }
+
+    // The bump allocator hands out frames in order, so everything in front of its next frame is
+    // either used or was skipped because it is reserved. Frames it got back are free again, reserved
+    // ones it handed out again are used.
+    pub fn take_over(&mut self, bump: &BumpAllocator) {
+        for number in 0..bump.next_frame().number {
+            if self.is_available(number) {
+                self.set_used(number, true);
+            }
+        }
+
+        self.reserved -= bump.reused_reserved();
+        for frame in bump.freed_frames() {
+            if bump.reserved().contains(frame) {
+                self.reserved -= 1;
+            }
+            if self.is_available(frame.number) {
+                self.set_used(frame.number, false);
+            }
+        }
+    }
+
+    pub fn zones(&self) -> impl Iterator<Item = ZoneInfo> + '_ {
+        ZONES
+            .iter()
+            .filter(|zone| self.frames[**zone as usize] > 0)
+            .map(|zone| ZoneInfo {
+                zone: *zone,
+                frames: self.frames[*zone as usize],
+                free: self.free[*zone as usize],
+            })
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        let total = self.frames.iter().sum::<u64>();
+        let free = self.free.iter().sum::<u64>();
+        FrameStats {
+            total,
+            free,
+            used: total - free - self.reserved,
+            reserved: self.reserved,
+        }
+    }
+
+    fn is_available(&self, number: u64) -> bool {
+        self.available
+            .get((number / 64) as usize)
+            .is_some_and(|word| word & (1 << (number % 64)) != 0)
+    }
+
+    fn is_used(&self, number: u64) -> bool {
+        self.bitmap[(number / 64) as usize] & (1 << (number % 64)) != 0
+    }
+
+    // Only for available frames, the free frames of their zone are counted
+    fn set_used(&mut self, number: u64, used: bool) {
+        if self.is_used(number) == used {
+            return;
+        }
+
+        let word = &mut self.bitmap[(number / 64) as usize];
+        let free = &mut self.free[Zone::containing(number * PAGE_SIZE) as usize];
+        if used {
+            *word |= 1 << (number % 64);
+            *free -= 1;
+        } else {
+            *word &= !(1 << (number % 64));
+            *free += 1;
+            self.next_free = self.next_free.min(number);
+        }
+    }
+}
+
+impl FrameAlloc for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysicalFrame> {
+        let first_word = (self.next_free / 64) as usize;
+        let (index, word) = self
+            .bitmap
+            .iter()
+            .enumerate()
+            .skip(first_word)
+            .find(|(_, word)| **word != u64::MAX)?;
+
+        let number = index as u64 * 64 + word.trailing_ones() as u64;
+        self.set_used(number, true);
+        self.next_free = number + 1;
+        Some(PhysicalFrame { number })
+    }
+
+    fn deallocate_frame(&mut self, frame: PhysicalFrame) {
+        assert!(
+            self.is_available(frame.number),
+            "Frame 0x{:x} is not managed by the frame allocator",
+            frame.start_address()
+        );
+        assert!(
+            self.is_used(frame.number),
+            "Frame 0x{:x} is not allocated",
+            frame.start_address()
+        );
+
+        self.set_used(frame.number, false);
+    }
+}
@@ -0,0 +1,310 @@
+use core::ops::{Range, RangeInclusive};
+
+use alloc::vec;
+use alloc::vec::Vec;
+use multiboot2::{MemoryArea, MemoryAreaType};
+
+use super::{
+    bump_alloc::BumpAllocator, FrameAlloc, FrameStats, PhysicalFrame, Zone, ZoneInfo, PAGE_SIZE,
+    ZONES,
+};
+use crate::memory::PhysicalAddress;
+
+// Blocks have 2^order frames, the largest ones are 4 MiB so they never cross a zone boundary
+pub const ORDERS: usize = 11;
+
+// Unlike the bitmap frame allocator, which would have to scan frame by frame to find a run of free
+// frames, it can hand out contiguous frames. Frames can't be accessed before they are mapped, so free blocks are tracked in a
+// bitmap per order instead of lists inside of the blocks. The bitmaps live on the heap, so this can
+// only be used once the heap exists.
+pub struct BuddyFrameAllocator {
+    // A bit per block of 2^order frames, set while the block is free
+    free_blocks: [Vec<u64>; ORDERS],
+    // Indexed by zone and order, the words of the bitmap in front of this one have no free block of
+    // the zone. Allocations start searching there instead of at the start of the zone.
+    first_free: [[usize; ORDERS]; ZONES.len()],
+    // A bit per frame, set for frames inside of an available memory area. Only those can ever be
+    // freed, holes and device memory are never managed by the allocator.
+    available: Vec<u64>,
+    // Indexed by zone
+    frames: [u64; ZONES.len()],
+    free: [u64; ZONES.len()],
+    // Frames that were never handed out because the kernel or the bootloader uses them
+    reserved: u64,
+}
+
+impl BuddyFrameAllocator {
+    pub const NAME: &'static str = "buddy";
+
+    pub fn new(areas: &[MemoryArea]) -> Self {
+        let available = || {
+            areas
+                .iter()
+                .filter(|a| a.typ() == MemoryAreaType::Available)
+        };
+        let frames = available()
+            .map(|a| a.end_address() / PAGE_SIZE)
+            .max()
+            .unwrap_or(0);
+
+        let mut allocator = BuddyFrameAllocator {
+            free_blocks: core::array::from_fn(|order| {
+                vec![0; (frames >> order).div_ceil(64) as usize + 1]
+            }),
+            first_free: [[0; ORDERS]; ZONES.len()],
+            frames: [0; ZONES.len()],
+            available: vec![0; frames.div_ceil(64) as usize],
+            free: [0; ZONES.len()],
+            reserved: 0,
+        };
+
+        // Only frames that are completely inside of an available area can be handed out
+        for area in available() {
+            let start = area.start_address().div_ceil(PAGE_SIZE);
+            let end = area.end_address() / PAGE_SIZE;
+            for number in start..end {
+                allocator.available[(number / 64) as usize] |= 1 << (number % 64);
+            }
+            allocator.free_range(start..end);
+        }
+        allocator.frames = allocator.free;
+        allocator
+    }
+
+    // Marks every frame that overlaps `range` as used
+    pub fn reserve(&mut self, range: RangeInclusive<PhysicalAddress>) {
+        for number in range.start() / PAGE_SIZE..=range.end() / PAGE_SIZE {
+            if self.take_frame(number) {
+                self.reserved += 1;
+            }
+        }
+    }
+
+    // The bump allocator hands out frames in order, so everything in front of its next frame is
+    // either used or was skipped because it is reserved. Frames it got back are free again, reserved
+    // ones it handed out again are used.
+    pub fn take_over(&mut self, bump: &BumpAllocator) {
+        for number in 0..bump.next_frame().number {
+            self.take_frame(number);
+        }
+
+        self.reserved -= bump.reused_reserved();
+        for frame in bump.freed_frames() {
+            if bump.reserved().contains(frame) {
+                self.reserved -= 1;
+            }
+            self.take_frame(frame.number);
+            self.free_range(frame.number..frame.number + 1);
+        }
+    }
+
+    /// Allocates `count` physically contiguous frames, the first one is aligned to `align` bytes
+    /// and the last one ends below `max_addr`. Normal zones are used before the DMA ones.
+    pub fn allocate_contiguous(
+        &mut self,
+        count: u64,
+        align: u64,
+        max_addr: PhysicalAddress,
+    ) -> Option<PhysicalFrame> {
+        assert!(count > 0, "Can't allocate 0 frames");
+        assert!(
+            align.is_power_of_two(),
+            "Alignment has to be a power of two"
+        );
+
+        let order = order_for(count).max(order_for(align.max(PAGE_SIZE) / PAGE_SIZE));
+        if order >= ORDERS {
+            return None;
+        }
+
+        let number = self.allocate_block(order, max_addr / PAGE_SIZE)?;
+
+        // Only `count` frames are handed out, the rest of the block goes back
+        self.free_range(number + count..number + (1 << order));
+        Some(PhysicalFrame { number })
+    }
+
+    pub fn deallocate_contiguous(&mut self, frame: PhysicalFrame, count: u64) {
+        for number in frame.number..frame.number + count {
+            assert!(
+                self.is_available(number),
+                "Frame 0x{:x} is not managed by the frame allocator",
+                number * PAGE_SIZE
+            );
+            assert!(
+                !self.is_frame_free(number),
+                "Frame 0x{:x} is not allocated",
+                number * PAGE_SIZE
+            );
+        }
+
+        self.free_range(frame.number..frame.number + count);
+    }
+
+    pub fn zones(&self) -> impl Iterator<Item = ZoneInfo> + '_ {
+        ZONES
+            .iter()
+            .filter(|zone| self.frames[**zone as usize] > 0)
+            .map(|zone| ZoneInfo {
+                zone: *zone,
+                frames: self.frames[*zone as usize],
+                free: self.free[*zone as usize],
+            })
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        let total = self.frames.iter().sum::<u64>();
+        let free = self.free.iter().sum::<u64>();
+        FrameStats {
+            total,
+            free,
+            used: total - free - self.reserved,
+            reserved: self.reserved,
+        }
+    }
+
+    // Finds a free block of at least `order` below frame `limit`, starting with the highest zone,
+    // and splits it until it has the right size
+    fn allocate_block(&mut self, order: usize, limit: u64) -> Option<u64> {
+        for zone in ZONES.iter().rev() {
+            let frames = zone.frames();
+            let Some(last_start) = limit.min(frames.end).checked_sub(1 << order) else {
+                continue;
+            };
+
+            for found_order in order..ORDERS {
+                // The lower half is kept when splitting, so a bigger block only has to start early
+                // enough for the requested size
+                let Some(number) = self.find_free(found_order, *zone, last_start + 1) else {
+                    continue;
+                };
+
+                self.set_free(number, found_order, false);
+                for split_order in (order..found_order).rev() {
+                    self.set_free(number + (1 << split_order), split_order, true);
+                }
+
+                self.free[*zone as usize] -= 1 << order;
+                return Some(number);
+            }
+        }
+        None
+    }
+
+    // Frees every frame of `frames` in the biggest aligned blocks that fit and merges them with their
+    // buddies
+    fn free_range(&mut self, frames: Range<u64>) {
+        let mut number = frames.start;
+        while number < frames.end {
+            let order = (number.trailing_zeros() as usize)
+                .min(ORDERS - 1)
+                .min(63 - (frames.end - number).leading_zeros() as usize);
+
+            self.free[Zone::containing(number * PAGE_SIZE) as usize] += 1 << order;
+            self.free_block(number, order);
+            number += 1 << order;
+        }
+    }
+
+    fn free_block(&mut self, mut number: u64, mut order: usize) {
+        while order + 1 < ORDERS {
+            let buddy = number ^ (1 << order);
+            if !self.is_free(buddy, order) {
+                break;
+            }
+
+            self.set_free(buddy, order, false);
+            number &= !(1 << order);
+            order += 1;
+        }
+        self.set_free(number, order, true);
+    }
+
+    // Takes a single frame out of the free block that contains it, returns false if it wasn't free
+    fn take_frame(&mut self, number: u64) -> bool {
+        let Some(order) = (0..ORDERS).find(|order| self.is_free(number >> order << order, *order))
+        else {
+            return false;
+        };
+
+        self.set_free(number >> order << order, order, false);
+        for split_order in (0..order).rev() {
+            let half = number >> split_order << split_order;
+            self.set_free(half ^ (1 << split_order), split_order, true);
+        }
+        self.free[Zone::containing(number * PAGE_SIZE) as usize] -= 1;
+        true
+    }
+
+    fn is_available(&self, number: u64) -> bool {
+        self.available
+            .get((number / 64) as usize)
+            .is_some_and(|word| word & (1 << (number % 64)) != 0)
+    }
+
+    fn is_frame_free(&self, number: u64) -> bool {
+        (0..ORDERS).any(|order| self.is_free(number >> order << order, order))
+    }
+
+    // First free block of `order` in `zone` if it starts before frame `end`
+    fn find_free(&mut self, order: usize, zone: Zone, end: u64) -> Option<u64> {
+        let bitmap = &self.free_blocks[order];
+        let first_free = &mut self.first_free[zone as usize][order];
+        let first = zone.frames().start.div_ceil(1 << order);
+        let first_word = (first / 64) as usize;
+
+        let found = bitmap
+            .iter()
+            .enumerate()
+            .skip(first_word.max(*first_free))
+            .find_map(|(index, word)| {
+                // The first word can start in the zone below
+                let word = if index == first_word {
+                    word & !((1 << (first % 64)) - 1)
+                } else {
+                    *word
+                };
+                (word != 0).then_some((index, word))
+            });
+        *first_free = found.map_or(bitmap.len(), |(index, _)| index);
+
+        let (index, word) = found?;
+        let number = (index as u64 * 64 + word.trailing_zeros() as u64) << order;
+        (number < end).then_some(number)
+    }
+
+    fn is_free(&self, number: u64, order: usize) -> bool {
+        let index = number >> order;
+        self.free_blocks[order]
+            .get((index / 64) as usize)
+            .is_some_and(|word| word & (1 << (index % 64)) != 0)
+    }
+
+    fn set_free(&mut self, number: u64, order: usize, free: bool) {
+        let index = number >> order;
+        let word = &mut self.free_blocks[order][(index / 64) as usize];
+        if free {
+            let first_free =
+                &mut self.first_free[Zone::containing(number * PAGE_SIZE) as usize][order];
+            *first_free = (*first_free).min((index / 64) as usize);
+            *word |= 1 << (index % 64);
+        } else {
+            *word &= !(1 << (index % 64));
+        }
+    }
+}
+
+impl FrameAlloc for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysicalFrame> {
+        self.allocate_contiguous(1, PAGE_SIZE, PhysicalAddress::MAX)
+    }
+
+    fn deallocate_frame(&mut self, frame: PhysicalFrame) {
+        self.deallocate_contiguous(frame, 1);
+    }
+}
+
+// Smallest order with at least `count` frames
+fn order_for(count: u64) -> usize {
+    count.next_power_of_two().trailing_zeros() as usize
+}
@@ -1,30 +1,55 @@
-use core::ops::RangeInclusive;
-
 use multiboot2::{MemoryArea, MemoryAreaType};
 
 use crate::println;
 
-use super::{FrameAlloc, PhysicalFrame, PAGE_SIZE};
+use super::{FrameAlloc, PhysicalFrame, ReservedRanges, PAGE_SIZE};
+
+// Enough for the boot page tables
+const MAX_FREED_FRAMES: usize = 8;
 
 pub struct BumpAllocator<'a> {
     next_frame: PhysicalFrame,
     current_area: Option<MemoryArea>,
     areas: &'a [MemoryArea],
-    kernel: RangeInclusive<u64>,
+    reserved: ReservedRanges,
+    // Deallocated frames are handed out again before new ones
+    freed: [Option<PhysicalFrame>; MAX_FREED_FRAMES],
+    // Frames of reserved ranges that were freed and handed out again, they are used now
+    reused_reserved: u64,
 }
 
 impl<'a> BumpAllocator<'a> {
-    pub fn new(areas: &'a [MemoryArea], kernel: RangeInclusive<u64>) -> Self {
+    pub fn new(areas: &'a [MemoryArea], reserved: ReservedRanges) -> Self {
         let mut allocator = BumpAllocator {
             next_frame: PhysicalFrame::by_addr(0),
             current_area: None,
             areas,
-            kernel,
+            reserved,
+            freed: [const { None }; MAX_FREED_FRAMES],
+            reused_reserved: 0,
         };
         allocator.next_area();
         allocator
     }
 
+    // Every available frame in front of this one was handed out or is reserved, except for the
+    // freed ones
+    pub fn next_frame(&self) -> &PhysicalFrame {
+        &self.next_frame
+    }
+
+    pub fn reserved(&self) -> &ReservedRanges {
+        &self.reserved
+    }
+
+    pub fn freed_frames(&self) -> impl Iterator<Item = &PhysicalFrame> {
+        self.freed.iter().flatten()
+    }
+
+    pub fn reused_reserved(&self) -> u64 {
+        self.reused_reserved
+    }
+
     fn next_area(&mut self) {
         self.current_area = self
             .areas
@@ -40,6 +65,13 @@ impl<'a> BumpAllocator<'a> {
 
 impl<'a> FrameAlloc for BumpAllocator<'a> {
     fn allocate_frame(&mut self) -> Option<PhysicalFrame> {
+        if let Some(frame) = self.freed.iter_mut().find_map(|f| f.take()) {
+            if self.reserved.contains(&frame) {
+                self.reused_reserved += 1;
+            }
+            return Some(frame);
+        }
+
         if let Some(area) = self.current_area {
             let current_frame = PhysicalFrame {
                 number: self.next_frame.number,
@@ -49,9 +81,8 @@ impl<'a> FrameAlloc for BumpAllocator<'a> {
                 number: self.next_frame.number + 1,
             };
 
-            // Next frame is within the kernel
-            if current_frame.within(self.kernel.clone()) {
-                // println!("Kernel");
+            // Next frame is used by the kernel or the bootloader
+            if self.reserved.contains(&current_frame) {
                 self.allocate_frame()
             // Next frame is behind current area
             } else if current_frame.end_address() >= area.end_address() {
@@ -71,8 +102,14 @@ impl<'a> FrameAlloc for BumpAllocator<'a> {
         }
     }
 
-    fn deallocate_frame(&mut self, _frame: PhysicalFrame) {
-        println!("[WARN] Trying to deallocate a frame, BumpAllocator doesn't support that");
-        // unimplemented!()
+    fn deallocate_frame(&mut self, frame: PhysicalFrame) {
+        if let Some(slot) = self.freed.iter_mut().find(|f| f.is_none()) {
+            *slot = Some(frame);
+        } else {
+            println!(
+                "[WARN] Leaking frame 0x{:x}, BumpAllocator can't keep more freed frames",
+                frame.start_address()
+            );
+        }
     }
 }
@@ -1,12 +1,24 @@
-use core::ops::RangeInclusive;
+use core::ops::{Range, RangeInclusive};
 
 use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
 
+use self::bump_alloc::BumpAllocator;
 use super::PhysicalAddress;
 
+#[cfg(feature = "bitmap-frame-allocator")]
+pub mod bitmap_alloc;
+#[cfg(not(feature = "bitmap-frame-allocator"))]
+pub mod buddy_alloc;
 pub mod bump_alloc;
 pub mod tiny_alloc;
 
+// Takes over from the bump allocator once the heap exists
+#[cfg(not(feature = "bitmap-frame-allocator"))]
+pub type MainFrameAllocator = buddy_alloc::BuddyFrameAllocator;
+#[cfg(feature = "bitmap-frame-allocator")]
+pub type MainFrameAllocator = bitmap_alloc::BitmapFrameAllocator;
+
 pub const PAGE_SIZE: u64 = 4096;
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
@@ -29,19 +41,176 @@ impl PhysicalFrame {
         self.number * PAGE_SIZE + PAGE_SIZE - 1
     }
 
+    // True if any byte of the frame is inside of `range`
     pub fn within(&self, range: RangeInclusive<u64>) -> bool {
-        return !((range.start().to_owned() > self.start_address()
-            && range.end().to_owned() > self.end_address())
-            || (range.start().to_owned() < self.start_address()
-                && range.end().to_owned() < self.end_address()));
+        range.start().to_owned() <= self.end_address()
+            && range.end().to_owned() >= self.start_address()
+    }
+}
+
+// Kernel, multiboot information and up to 14 boot modules
+pub const MAX_RESERVED_RANGES: usize = 16;
+
+// Physical memory that is in use before any frame is allocated. It is collected before there is a
+// heap, so it has a fixed capacity.
+#[derive(Debug, Clone)]
+pub struct ReservedRanges {
+    ranges: [RangeInclusive<PhysicalAddress>; MAX_RESERVED_RANGES],
+    len: usize,
+}
+
+impl ReservedRanges {
+    pub fn new() -> Self {
+        ReservedRanges {
+            ranges: core::array::from_fn(|_| 0..=0),
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, range: RangeInclusive<PhysicalAddress>) {
+        assert!(
+            self.len < MAX_RESERVED_RANGES,
+            "Too many reserved memory ranges"
+        );
+        self.ranges[self.len] = range;
+        self.len += 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RangeInclusive<PhysicalAddress>> {
+        self.ranges[..self.len].iter()
+    }
+
+    pub fn contains(&self, frame: &PhysicalFrame) -> bool {
+        self.iter().any(|range| frame.within(range.clone()))
+    }
+}
+
+impl Default for ReservedRanges {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    // Below 16 MiB, reachable by ISA DMA
+    Dma,
+    // Below 4 GiB, reachable by devices with 32 bit addresses
+    Dma32,
+    High,
+}
+
+pub const ZONES: [Zone; 3] = [Zone::Dma, Zone::Dma32, Zone::High];
+
+impl Zone {
+    pub fn containing(address: PhysicalAddress) -> Zone {
+        *ZONES
+            .iter()
+            .find(|zone| zone.range().contains(&address))
+            .unwrap_or(&Zone::High)
+    }
+
+    pub fn range(&self) -> Range<PhysicalAddress> {
+        match self {
+            Zone::Dma => 0..16 * 1024 * 1024,
+            Zone::Dma32 => 16 * 1024 * 1024..4 * 1024 * 1024 * 1024,
+            Zone::High => 4 * 1024 * 1024 * 1024..PhysicalAddress::MAX,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Zone::Dma => "DMA",
+            Zone::Dma32 => "DMA32",
+            Zone::High => "High",
+        }
+    }
+
+    // Only the buddy allocator works with frame numbers per zone
+    #[cfg(not(feature = "bitmap-frame-allocator"))]
+    fn frames(&self) -> Range<u64> {
+        self.range().start / PAGE_SIZE..self.range().end / PAGE_SIZE
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct ZoneInfo {
+    pub zone: Zone,
+    // Frames in available memory areas
+    pub frames: u64,
+    pub free: u64,
+}
+
+// Frames in available memory
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    pub total: u64,
+    pub free: u64,
+    pub used: u64,
+    pub reserved: u64,
+}
+
 pub trait FrameAlloc {
     fn allocate_frame(&mut self) -> Option<PhysicalFrame>;
     fn deallocate_frame(&mut self, frame: PhysicalFrame);
 }
 
+// The bump allocator is used until the heap exists, then the main allocator (which keeps its
+// bitmaps on the heap) takes over. The main allocator is boxed, it is much bigger than a pointer and
+// the heap exists by the time it is created.
+pub struct KernelFrameAllocator {
+    bump: BumpAllocator<'static>,
+    main: Option<Box<MainFrameAllocator>>,
+}
+
+impl KernelFrameAllocator {
+    pub fn new(bump: BumpAllocator<'static>) -> Self {
+        KernelFrameAllocator { bump, main: None }
+    }
+
+    pub fn switch_to_main(&mut self, mut main: Box<MainFrameAllocator>) {
+        assert!(
+            self.main.is_none(),
+            "Main frame allocator took over already"
+        );
+        main.take_over(&self.bump);
+        self.main = Some(main);
+    }
+
+    // Only the main allocator keeps count
+    pub fn stats(&self) -> Option<FrameStats> {
+        self.main.as_ref().map(|main| main.stats())
+    }
+
+    // Copied out so they can be printed without holding the memory manager, empty until the main
+    // allocator took over
+    pub fn zones(&self) -> [Option<ZoneInfo>; ZONES.len()] {
+        let mut zones = [None; ZONES.len()];
+        if let Some(main) = &self.main {
+            for (slot, zone) in zones.iter_mut().zip(main.zones()) {
+                *slot = Some(zone);
+            }
+        }
+        zones
+    }
+}
+
+impl FrameAlloc for KernelFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysicalFrame> {
+        match &mut self.main {
+            Some(main) => main.allocate_frame(),
+            None => self.bump.allocate_frame(),
+        }
+    }
+
+    fn deallocate_frame(&mut self, frame: PhysicalFrame) {
+        match &mut self.main {
+            Some(main) => main.deallocate_frame(frame),
+            None => self.bump.deallocate_frame(frame),
+        }
+    }
+}
+
 pub struct FrameIter {
     start: PhysicalFrame,
     end: PhysicalFrame,
@@ -0,0 +1,209 @@
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(feature = "heap-track")]
+use allocator::{CallSite, CallSiteReport, BACKTRACE_DEPTH};
+use allocator::{HeapStats, LinkedAllocatorNode};
+
+use crate::memory::frames::PAGE_SIZE;
+use crate::memory::manager::{self, MemoryManager};
+use crate::memory::paging::entry::EntryFlags;
+use crate::memory::paging::{Page, PageRange};
+use crate::memory::VirtualAddress;
+use crate::serial_println;
+
+pub const HEAP_START: VirtualAddress = 0xffff_ff00_0000_0000;
+pub const HEAP_INITIAL_SIZE: u64 = PAGE_SIZE;
+// The heap never grows past HEAP_START + HEAP_MAX_SIZE
+pub const HEAP_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+#[cfg(not(feature = "slab-allocator"))]
+type HeapAllocator = allocator::LockedLinkedListAllocator;
+#[cfg(feature = "slab-allocator")]
+type HeapAllocator = allocator::LockedSlabAllocator;
+
+#[global_allocator]
+static ALLOCATOR: HeapAllocator = HeapAllocator::new();
+
+// First address after the last mapped heap page, only changes while the allocator is locked
+static HEAP_END: AtomicU64 = AtomicU64::new(HEAP_START);
+
+// Maps pages until the page-aligned `end` is reached, returns false and maps nothing if there aren't
+// enough frames left
+fn map_until(manager: &mut MemoryManager, end: VirtualAddress) -> bool {
+    let heap_end = HEAP_END.load(Ordering::Relaxed);
+    if heap_end >= end {
+        return true;
+    }
+
+    let mapped = manager
+        .map_range(PageRange::containing(heap_end, end), EntryFlags::WRITABLE)
+        .is_ok();
+    if mapped {
+        HEAP_END.store(end, Ordering::Relaxed);
+    }
+    mapped
+}
+
+pub(super) fn init() {
+    manager::with(|manager| {
+        assert!(
+            map_until(manager, HEAP_START + HEAP_INITIAL_SIZE),
+            "Out of memory"
+        );
+
+        // The offset of the last byte of the first page doesn't fit in a table index
+        let address = HEAP_START + PAGE_SIZE - 1;
+        let frame = manager
+            .translate_page(Page::containing_address(address))
+            .unwrap();
+        assert_eq!(
+            manager.translate(address),
+            Some(frame.start_address() + PAGE_SIZE - 1),
+            "Heap address translated to the wrong physical address"
+        );
+    });
+
+    let node: &mut LinkedAllocatorNode = unsafe { &mut *(HEAP_START as *mut _) };
+    *node = LinkedAllocatorNode::new(HEAP_INITIAL_SIZE as usize);
+
+    ALLOCATOR.with(|allocator| {
+        allocator.init(node);
+        allocator.set_grow_handler(grow);
+        #[cfg(feature = "heap-track")]
+        allocator.set_track_handler(track);
+    });
+}
+
+// Amount of bytes currently mapped for the heap
+pub fn size() -> u64 {
+    HEAP_END.load(Ordering::Relaxed) - HEAP_START
+}
+
+pub fn stats() -> HeapStats {
+    ALLOCATOR.with(|allocator| allocator.stats())
+}
+
+pub fn print_stats() {
+    let stats = stats();
+    serial_println!(
+        "Heap: {} bytes total, {} used in {} allocations, {} free (largest block {}, {}% \
+        fragmented), {} nodes",
+        stats.total,
+        stats.used,
+        stats.allocations,
+        stats.free,
+        stats.largest_free,
+        stats.fragmentation(),
+        stats.nodes
+    );
+}
+
+// Callers `print_leaks` reports on, the reports are kept on the stack
+#[cfg(feature = "heap-track")]
+const LEAK_REPORT_CALLERS: usize = 32;
+
+// Prints the outstanding allocations grouped by call site over serial, the allocator stays locked
+// while printing
+#[cfg(feature = "heap-track")]
+pub fn print_leaks() {
+    ALLOCATOR.with(|allocator| {
+        let mut reports = [CallSiteReport::default(); LEAK_REPORT_CALLERS];
+        let (reports, skipped) = allocator.leak_report(&mut reports);
+        for report in reports {
+            serial_println!(
+                "0x{:x}: {} allocations, {} bytes, oldest at tsc {} from {:x?}",
+                report.caller,
+                report.allocations,
+                report.bytes,
+                report.oldest.timestamp,
+                report.oldest.backtrace
+            );
+        }
+        if skipped > 0 {
+            serial_println!("{} allocations from other callers", skipped);
+        }
+    });
+}
+
+// Only reached by infallible allocations (Box::new, Vec::push, ...), the try_* variants get an error
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    #[cfg(feature = "heap-track")]
+    print_leaks();
+
+    let stats = stats();
+    panic!(
+        "Out of memory while allocating {:?}, heap at 0x{:x} is {} bytes (max {} bytes), \
+        {} bytes free in {} nodes, largest free block is {} bytes",
+        layout,
+        HEAP_START,
+        size(),
+        HEAP_MAX_SIZE,
+        stats.free,
+        stats.nodes - stats.allocations,
+        stats.largest_free
+    )
+}
+
+// Called by the allocator (with its lock held) when no free node fits an allocation
+fn grow(heap_end: usize, min_size: usize) -> usize {
+    let heap_end = heap_end as VirtualAddress;
+    let new_end = (heap_end + min_size as u64).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+    if new_end > HEAP_START + HEAP_MAX_SIZE {
+        return 0;
+    }
+
+    // Locks the memory manager behind the allocator, see the lock order in memory::manager
+    manager::with(|manager| map_until(manager, new_end));
+    (HEAP_END.load(Ordering::Relaxed) - heap_end) as usize
+}
+
+// Frames between `track` and the code that allocated: the allocator, the lock around it and
+// __rust_alloc
+#[cfg(feature = "heap-track")]
+const TRACK_SKIPPED_FRAMES: usize = 4;
+
+// Called by the allocator (with its lock held) for every allocation
+#[cfg(feature = "heap-track")]
+fn track() -> CallSite {
+    let mut frames = [0; TRACK_SKIPPED_FRAMES + 1 + BACKTRACE_DEPTH];
+    backtrace(&mut frames);
+
+    let mut backtrace = [0; BACKTRACE_DEPTH];
+    backtrace.copy_from_slice(&frames[TRACK_SKIPPED_FRAMES + 1..]);
+    CallSite {
+        caller: frames[TRACK_SKIPPED_FRAMES],
+        backtrace,
+        timestamp: unsafe { core::arch::x86_64::_rdtsc() },
+    }
+}
+
+// Fills `frames` with return addresses by walking the frame pointer chain. Only frames on the boot
+// stack are followed, so a frame pointer that is garbage (no frame pointers) can't fault.
+#[cfg(feature = "heap-track")]
+fn backtrace(frames: &mut [usize]) {
+    unsafe extern "C" {
+        static stack_end: u8;
+    }
+
+    let stack_top = core::ptr::addr_of!(stack_end) as usize;
+    let (mut rbp, rsp): (usize, usize);
+    unsafe {
+        core::arch::asm!("mov {}, rbp", out(reg) rbp);
+        core::arch::asm!("mov {}, rsp", out(reg) rsp);
+    }
+
+    for frame in frames {
+        if rbp < rsp || rbp + 16 > stack_top || rbp % 8 != 0 {
+            break;
+        }
+
+        let next = unsafe { *(rbp as *const usize) };
+        *frame = unsafe { *((rbp + 8) as *const usize) };
+        if next <= rbp {
+            break;
+        }
+        rbp = next;
+    }
+}
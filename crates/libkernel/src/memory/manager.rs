@@ -0,0 +1,90 @@
+use alloc::boxed::Box;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::memory::frames::bump_alloc::BumpAllocator;
+use crate::memory::frames::{
+    FrameStats, KernelFrameAllocator, MainFrameAllocator, PhysicalFrame, ZoneInfo, ZONES,
+};
+use crate::memory::paging::entry::EntryFlags;
+use crate::memory::paging::mapper::{ActivePageTable, MapError};
+use crate::memory::paging::{Page, PageRange};
+use crate::memory::{PhysicalAddress, VirtualAddress};
+
+// The heap grows through the memory manager while the allocator is locked, so the lock order is
+// allocator, then memory manager. Nothing may allocate while it holds the memory manager.
+static MEMORY_MANAGER: Mutex<Option<MemoryManager>> = Mutex::new(None);
+
+// Owns the physical memory and the page tables once the kernel is remapped. Changed mappings are
+// flushed from the TLB before returning.
+pub struct MemoryManager {
+    frame_allocator: KernelFrameAllocator,
+    active_table: ActivePageTable,
+}
+
+impl MemoryManager {
+    // None until the main frame allocator took over
+    pub fn frame_stats(&self) -> Option<FrameStats> {
+        self.frame_allocator.stats()
+    }
+
+    // Maps every page of `range` to a new frame
+    pub fn map_range(&mut self, range: PageRange, flags: EntryFlags) -> Result<(), MapError> {
+        self.active_table
+            .map_range(range, flags, &mut self.frame_allocator)
+            .map(|batch| batch.flush())
+    }
+
+    // Huge pages are used wherever the pages and frames are aligned to them
+    #[cfg(feature = "direct-map")]
+    pub fn map_range_to(
+        &mut self,
+        range: PageRange,
+        frame: PhysicalFrame,
+        flags: EntryFlags,
+    ) -> Result<(), MapError> {
+        self.active_table
+            .map_range_to(range, frame, flags, &mut self.frame_allocator)
+            .map(|batch| batch.flush())
+    }
+
+    // See KernelFrameAllocator::zones
+    pub fn zones(&self) -> [Option<ZoneInfo>; ZONES.len()] {
+        self.frame_allocator.zones()
+    }
+
+    pub fn translate(&self, address: VirtualAddress) -> Option<PhysicalAddress> {
+        self.active_table.translate(address)
+    }
+
+    pub fn translate_page(&self, page: Page) -> Option<PhysicalFrame> {
+        self.active_table.translate_page(page)
+    }
+
+    // Lets the main frame allocator hand out the frames from now on. It can only be created and
+    // boxed once the heap exists.
+    pub(super) fn switch_to_main(&mut self, frame_allocator: Box<MainFrameAllocator>) {
+        self.frame_allocator.switch_to_main(frame_allocator);
+    }
+}
+
+pub(super) fn init(frame_allocator: BumpAllocator<'static>, active_table: ActivePageTable) {
+    *MEMORY_MANAGER.lock() = Some(MemoryManager {
+        frame_allocator: KernelFrameAllocator::new(frame_allocator),
+        active_table,
+    });
+}
+
+// Runs `f` with the memory manager locked and interrupts disabled, so an interrupt handler can't
+// deadlock on it. `f` must not allocate, growing the heap needs the memory manager.
+pub fn with<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut MemoryManager) -> R,
+{
+    interrupts::without_interrupts(|| {
+        let mut manager = MEMORY_MANAGER.lock();
+        f(manager
+            .as_mut()
+            .expect("Memory manager is not initialized yet"))
+    })
+}
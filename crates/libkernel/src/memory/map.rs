@@ -0,0 +1,148 @@
+use core::fmt;
+
+use alloc::format;
+use alloc::vec::Vec;
+use multiboot2::{MemoryArea, MemoryAreaType};
+use spin::Once;
+
+use crate::memory::PhysicalAddress;
+use crate::println;
+
+static MEMORY_MAP: Once<MemoryMap> = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AreaKind {
+    Available,
+    Reserved,
+    // Holds the ACPI tables, usable once they are parsed
+    AcpiReclaimable,
+    // Has to be preserved on hibernation
+    Nvs,
+    Defective,
+    // Type the multiboot specification doesn't know about
+    Unknown(u32),
+}
+
+pub const AREA_KINDS: [AreaKind; 5] = [
+    AreaKind::Available,
+    AreaKind::Reserved,
+    AreaKind::AcpiReclaimable,
+    AreaKind::Nvs,
+    AreaKind::Defective,
+];
+
+impl AreaKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AreaKind::Available => "Available",
+            AreaKind::Reserved => "Reserved",
+            AreaKind::AcpiReclaimable => "ACPI reclaimable",
+            AreaKind::Nvs => "ACPI NVS",
+            AreaKind::Defective => "Defective",
+            AreaKind::Unknown(_) => "Unknown",
+        }
+    }
+}
+
+impl From<MemoryAreaType> for AreaKind {
+    fn from(typ: MemoryAreaType) -> Self {
+        match typ {
+            MemoryAreaType::Available => AreaKind::Available,
+            MemoryAreaType::Reserved => AreaKind::Reserved,
+            MemoryAreaType::AcpiAvailable => AreaKind::AcpiReclaimable,
+            MemoryAreaType::ReservedHibernate => AreaKind::Nvs,
+            MemoryAreaType::Defective => AreaKind::Defective,
+            MemoryAreaType::Custom(typ) => AreaKind::Unknown(typ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryMapArea {
+    pub start: PhysicalAddress,
+    // Exclusive
+    pub end: PhysicalAddress,
+    pub kind: AreaKind,
+}
+
+impl MemoryMapArea {
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+// The memory map passed by the bootloader, sorted by address
+pub struct MemoryMap {
+    areas: Vec<MemoryMapArea>,
+}
+
+impl MemoryMap {
+    pub fn new(areas: &[MemoryArea]) -> Self {
+        let mut areas: Vec<MemoryMapArea> = areas
+            .iter()
+            .filter(|area| area.size() > 0)
+            .map(|area| MemoryMapArea {
+                start: area.start_address(),
+                end: area.end_address(),
+                kind: MemoryAreaType::from(area.typ()).into(),
+            })
+            .collect();
+        areas.sort_unstable_by_key(|area| area.start);
+
+        MemoryMap { areas }
+    }
+
+    #[cfg(feature = "direct-map")]
+    pub fn areas(&self) -> &[MemoryMapArea] {
+        &self.areas
+    }
+
+    // Bytes in areas of `kind`
+    pub fn total(&self, kind: AreaKind) -> u64 {
+        self.areas
+            .iter()
+            .filter(|area| area.kind == kind)
+            .map(|area| area.size())
+            .sum()
+    }
+
+    pub fn print(&self) {
+        println!("{:<18} {:<18} {:>10}  Type", "Start", "End", "Size");
+        for area in &self.areas {
+            println!(
+                "{:#018x} {:#018x} {:>10}  {}",
+                area.start,
+                area.end,
+                Size(area.size()),
+                area.kind.name()
+            );
+        }
+
+        for kind in AREA_KINDS {
+            let total = self.total(kind);
+            if total > 0 {
+                println!("{}: {}", kind.name(), Size(total));
+            }
+        }
+    }
+}
+
+// Formats an amount of bytes with a binary unit
+pub struct Size(pub u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+        // Rounded down to the biggest unit that fits at least once
+        let unit = (0..UNITS.len())
+            .rev()
+            .find(|unit| self.0 >> (10 * unit) > 0)
+            .unwrap_or(0);
+        f.pad(&format!("{} {}", self.0 >> (10 * unit), UNITS[unit]))
+    }
+}
+
+pub(super) fn init(areas: &[MemoryArea]) -> &'static MemoryMap {
+    MEMORY_MAP.call_once(|| MemoryMap::new(areas))
+}
@@ -1,11 +1,15 @@
 use core::ops::RangeInclusive;
 
 use crate::memory::frames::bump_alloc::BumpAllocator;
-use crate::memory::frames::{FrameIter, PhysicalFrame, PAGE_SIZE};
+use crate::memory::frames::{
+    FrameIter, FrameStats, MainFrameAllocator, PhysicalFrame, ReservedRanges, PAGE_SIZE,
+};
+use crate::memory::map::Size;
 use crate::memory::paging::entry::EntryFlags;
-use crate::memory::paging::mapper::ActivePageTable;
+use crate::memory::paging::mapper::{ActivePageTable, MapError};
 use crate::memory::paging::Page;
 use crate::println;
+use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::vec;
 use multiboot2::{BootInformation, ElfSectionFlags};
@@ -13,24 +17,31 @@ use x86_64::registers::control::{Cr0, Cr0Flags};
 use x86_64::registers::model_specific::{Efer, EferFlags};
 
 use self::frames::FrameAlloc;
+use self::paging::dump;
 use self::paging::inactive::InactivePageTable;
 use self::paging::temporary::TemporaryPage;
-use allocator::{LinkedAllocatorNode, LinkedListAllocator};
 
+#[cfg(feature = "direct-map")]
+pub mod direct_map;
 pub mod frames;
+pub mod heap;
+pub mod manager;
+pub mod map;
 pub mod paging;
+pub mod region;
 
 pub type PhysicalAddress = u64;
 pub type VirtualAddress = u64;
 
 pub const TABLE_SIZE: usize = 512;
 
-#[global_allocator]
-static mut ALLOCATOR: LinkedListAllocator = LinkedListAllocator::new();
+// Used to edit tables that aren't reachable through the active one
+pub const TEMPORARY_PAGE_ADDRESS: VirtualAddress = 0x1337a110c;
 
-pub(super) fn init(boot_info: &BootInformation) -> () {
+pub(super) fn init(boot_info: &'static BootInformation) -> () {
     enable_write_protect_bit();
     enable_nxe_bit();
+    paging::flush::init();
 
     println!("[INFO] Remapping the kernel...");
     let memory_areas = boot_info.memory_map_tag().unwrap().memory_areas();
@@ -43,7 +54,8 @@ pub(super) fn init(boot_info: &BootInformation) -> () {
         .unwrap()
         ..=elf_sections.map(|s| s.end_address()).max().unwrap();
 
-    let mut frame_allocator = BumpAllocator::new(memory_areas, kernel);
+    let reserved = reserved_ranges(boot_info, kernel);
+    let mut frame_allocator = BumpAllocator::new(memory_areas, reserved.clone());
     let mut active_page = unsafe { ActivePageTable::new() };
 
     remap_kernel(&mut frame_allocator, &mut active_page, boot_info);
@@ -51,16 +63,101 @@ pub(super) fn init(boot_info: &BootInformation) -> () {
     println!("[OK] Kernel remapped!");
     println!("[INFO] Initializing linked list allocator...");
 
-    let page = Page::containing_address(0xffff_ff00_0000_0000);
-    let frame = frame_allocator.allocate_frame().expect("Out of memory");
-    active_page.map_to(page, frame, EntryFlags::WRITABLE, &mut frame_allocator);
-    let node: &mut LinkedAllocatorNode = unsafe { &mut *(page.start_address() as *mut _) };
+    manager::init(frame_allocator, active_page);
+    heap::init();
 
-    *node = LinkedAllocatorNode::new(PAGE_SIZE as usize);
+    println!("[OK] Linked list allocator initialized!");
+    heap::print_stats();
 
-    unsafe { ALLOCATOR.init(node) };
+    let memory_map = map::init(memory_areas);
 
-    println!("[OK] Linked list allocator initialized!");
+    #[cfg(feature = "direct-map")]
+    {
+        println!("[INFO] Mapping physical memory...");
+        manager::with(|manager| direct_map::init(manager, memory_map));
+        println!(
+            "[OK] Physical memory mapped at 0x{:x}!",
+            direct_map::PHYSICAL_MEMORY_OFFSET
+        );
+    }
+
+    println!(
+        "[INFO] Initializing {} frame allocator...",
+        MainFrameAllocator::NAME
+    );
+
+    // Growing the heap for the bitmaps still takes frames from the bump allocator, so the memory
+    // manager can't be locked while creating it
+    let mut frame_allocator = Box::new(MainFrameAllocator::new(memory_areas));
+    for range in reserved.iter() {
+        frame_allocator.reserve(range.clone());
+    }
+
+    // Frames the bump allocator handed out are only taken out of the zones by the switch
+    let zones = manager::with(|manager| {
+        manager.switch_to_main(frame_allocator);
+        manager.zones()
+    });
+    for zone in zones.iter().flatten() {
+        println!(
+            "Zone {}: {} frames, {} free",
+            zone.zone.name(),
+            zone.frames,
+            zone.free
+        );
+    }
+
+    println!(
+        "[OK] {} frame allocator initialized!",
+        MainFrameAllocator::NAME
+    );
+
+    memory_map.print();
+    if let Some(stats) = frame_stats() {
+        println!(
+            "Frames: {} total ({}), {} free, {} used, {} reserved",
+            stats.total,
+            Size(stats.total * PAGE_SIZE),
+            stats.free,
+            stats.used,
+            stats.reserved
+        );
+    }
+}
+
+// Frames of available memory that are free, used or reserved by the kernel and the bootloader.
+// None during boot, before the main frame allocator took over.
+pub fn frame_stats() -> Option<FrameStats> {
+    manager::with(|manager| manager.frame_stats())
+}
+
+// Memory that is in use before the first frame is allocated. The boot page tables and stack are part
+// of the kernel.
+fn reserved_ranges(boot_info: &BootInformation, kernel: RangeInclusive<u64>) -> ReservedRanges {
+    let mut reserved = ReservedRanges::new();
+    reserved.push(kernel);
+    reserved.push(boot_info.start_address() as u64..=boot_info.end_address() as u64 - 1);
+
+    for module in boot_info.module_tags() {
+        if module.module_size() > 0 {
+            reserved.push(module.start_address() as u64..=module.end_address() as u64 - 1);
+        }
+    }
+    reserved
+}
+
+// Frames of the P3 and P2 tables set up in boot.asm, the P4 is the active table until the kernel is
+// remapped
+fn boot_table_frames() -> [PhysicalFrame; 2] {
+    unsafe extern "C" {
+        static p3_table: u8;
+        static p2_table: u8;
+    }
+
+    [
+        PhysicalFrame::by_addr(core::ptr::addr_of!(p3_table) as u64),
+        PhysicalFrame::by_addr(core::ptr::addr_of!(p2_table) as u64),
+    ]
 }
 
 fn remap_kernel<A: FrameAlloc>(
@@ -68,13 +165,15 @@ fn remap_kernel<A: FrameAlloc>(
     active_table: &mut ActivePageTable,
     boot_info: &BootInformation,
 ) {
-    let mut temp_page = TemporaryPage::new(Page::containing_address(0x1337a110c), allocator);
+    let mut temp_page =
+        TemporaryPage::new(Page::containing_address(TEMPORARY_PAGE_ADDRESS), allocator);
 
     let mut new_table = {
         let frame = allocator.allocate_frame().expect("Out of memory");
         InactivePageTable::new(frame, active_table, &mut temp_page)
     };
 
+    // The new table isn't active yet, nothing of it can be in the TLB
     active_table.with(&mut new_table, &mut temp_page, |mapper| {
         for section in boot_info.elf_sections().unwrap() {
             if !section.is_allocated() {
@@ -102,20 +201,47 @@ fn remap_kernel<A: FrameAlloc>(
 
             let start = PhysicalFrame::by_addr(section.start_address());
             let end = PhysicalFrame::by_addr(section.end_address());
+            mapper
+                .identity_map_range(start, end, flags, allocator)
+                .expect("Cannot map the kernel")
+                .ignore();
+        }
 
-            for frame in FrameIter::new(start, end) {
-                mapper.identity_map(frame, flags, allocator);
+        // The memory map is still read by the frame allocator after the switch
+        let multiboot_start = PhysicalFrame::by_addr(boot_info.start_address() as u64);
+        let multiboot_end = PhysicalFrame::by_addr(boot_info.end_address() as u64 - 1 + PAGE_SIZE);
+        for frame in FrameIter::new(multiboot_start, multiboot_end) {
+            match mapper.identity_map(frame, EntryFlags::NOEXECUTE, allocator) {
+                Ok(flush) => flush.ignore(),
+                // Shares a frame with the kernel
+                Err(MapError::AlreadyMapped(_)) => {}
+                Err(error) => panic!("Cannot map the multiboot information: {:?}", error),
             }
         }
 
         let vga_text = PhysicalFrame::by_addr(0xb8000);
-        mapper.identity_map(vga_text, EntryFlags::WRITABLE, allocator);
+        mapper
+            .identity_map(vga_text, EntryFlags::WRITABLE, allocator)
+            .expect("Cannot map the VGA buffer")
+            .ignore();
     });
 
+    println!("Kernel mappings:");
+    dump::print_inactive(active_table, &mut new_table, &mut temp_page);
+
     let old_table = active_table.switch(new_table);
 
+    // Unmapping the old P4 and the boot P3 and P2 frees them, their pages become a guard page below
+    // the boot stack
     let old_page = Page::containing_address(old_table.p4_frame.start_address());
-    active_table.unmap(old_page, allocator);
+    active_table
+        .unmap(old_page, allocator)
+        .expect("Old P4 table is not mapped");
+    for frame in boot_table_frames() {
+        active_table
+            .unmap(Page::containing_address(frame.start_address()), allocator)
+            .expect("Boot page table is not mapped");
+    }
 }
 
 fn enable_write_protect_bit() {
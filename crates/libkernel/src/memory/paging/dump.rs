@@ -0,0 +1,238 @@
+use core::fmt;
+
+use crate::memory::frames::PAGE_SIZE;
+use crate::memory::{PhysicalAddress, VirtualAddress, TABLE_SIZE};
+use crate::println;
+
+use super::{
+    entry::{EntryFlags, PageEntry},
+    inactive::InactivePageTable,
+    mapper::{ActivePageTable, Mapper},
+    temporary::TemporaryPage,
+    HugePageSize, Page,
+};
+
+// The last P4 entry maps the tables themselves
+const RECURSIVE_INDEX: usize = TABLE_SIZE - 1;
+
+// Set by the CPU on access, so they would split ranges that are mapped the same way
+const IGNORED_FLAGS: EntryFlags = EntryFlags::ACCESSED.union(EntryFlags::DIRTY);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingKind {
+    Page,
+    Huge(HugePageSize),
+    // The P4 entry pointing back to the P4 table
+    Recursive,
+}
+
+// Virtually and physically contiguous pages of the same kind that are mapped with the same flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    pub start: VirtualAddress,
+    // Bytes, the recursive slot reaches the end of the address space
+    pub size: u64,
+    pub frame: PhysicalAddress,
+    pub flags: EntryFlags,
+    pub kind: MappingKind,
+}
+
+impl Mapping {
+    // Extends `self` by `next` if it continues it
+    fn merge(&mut self, next: &Mapping) -> bool {
+        let continues = self.kind == next.kind
+            && self.flags == next.flags
+            && self.start.wrapping_add(self.size) == next.start
+            && self.frame + self.size == next.frame;
+        if continues {
+            self.size += next.size;
+        }
+        continues
+    }
+}
+
+// Formatted like `0x100000-0x10a000 -> 0x100000 R-X G`
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let writable = if self.flags.contains(EntryFlags::WRITABLE) {
+            "W"
+        } else {
+            "-"
+        };
+        let executable = if self.flags.contains(EntryFlags::NOEXECUTE) {
+            "-"
+        } else {
+            "X"
+        };
+        write!(
+            f,
+            "{:#x}-{:#x} -> {:#x} R{}{}",
+            self.start,
+            self.start as u128 + self.size as u128,
+            self.frame,
+            writable,
+            executable
+        )?;
+
+        for (flag, name) in [
+            (EntryFlags::USERACCESSIBLE, "U"),
+            (EntryFlags::GLOBAL, "G"),
+            (EntryFlags::WRITETHROUGH, "WT"),
+            (EntryFlags::NO_CACHE, "NC"),
+        ] {
+            if self.flags.contains(flag) {
+                write!(f, " {}", name)?;
+            }
+        }
+
+        match self.kind {
+            MappingKind::Page => Ok(()),
+            MappingKind::Huge(HugePageSize::Size2MiB) => write!(f, " 2M"),
+            MappingKind::Huge(HugePageSize::Size1GiB) => write!(f, " 1G"),
+            MappingKind::Recursive => write!(f, " recursive"),
+        }
+    }
+}
+
+// Calls `f` for every mapping of the table `mapper` edits, sorted by address. The recursive slot is
+// reported but not walked.
+pub fn for_each_mapping<F: FnMut(&Mapping)>(mapper: &Mapper, mut f: F) {
+    let mut current: Option<Mapping> = None;
+    let mut add = |mapping: Mapping| {
+        if let Some(current) = &mut current
+            && current.merge(&mapping)
+        {
+            return;
+        }
+        if let Some(previous) = current.replace(mapping) {
+            f(&previous);
+        }
+    };
+
+    let p4 = mapper.p4();
+    for i4 in 0..TABLE_SIZE {
+        if i4 == RECURSIVE_INDEX {
+            if let Some(mapping) = leaf(&p4[i4], i4, 0, 0, 0, MappingKind::Recursive) {
+                add(mapping);
+            }
+            continue;
+        }
+        let Some(p3) = p4.next_level(i4 as u64) else {
+            continue;
+        };
+
+        for i3 in 0..TABLE_SIZE {
+            let huge = MappingKind::Huge(HugePageSize::Size1GiB);
+            let Some(p2) = p3.next_level(i3 as u64) else {
+                if let Some(mapping) = leaf(&p3[i3], i4, i3, 0, 0, huge) {
+                    add(mapping);
+                }
+                continue;
+            };
+
+            for i2 in 0..TABLE_SIZE {
+                let huge = MappingKind::Huge(HugePageSize::Size2MiB);
+                let Some(p1) = p2.next_level(i2 as u64) else {
+                    if let Some(mapping) = leaf(&p2[i2], i4, i3, i2, 0, huge) {
+                        add(mapping);
+                    }
+                    continue;
+                };
+
+                for i1 in 0..TABLE_SIZE {
+                    if let Some(mapping) = leaf(&p1[i1], i4, i3, i2, i1, MappingKind::Page) {
+                        add(mapping);
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(last) = current {
+        f(&last);
+    }
+}
+
+// The mapping of a present entry that maps memory instead of a table
+fn leaf(
+    entry: &PageEntry,
+    i4: usize,
+    i3: usize,
+    i2: usize,
+    i1: usize,
+    kind: MappingKind,
+) -> Option<Mapping> {
+    let frame = entry.pointed_frame()?;
+    let pages = match kind {
+        MappingKind::Page => 1,
+        MappingKind::Huge(size) => {
+            if !entry.flags().contains(EntryFlags::HUGEPAGE) {
+                return None;
+            }
+            size.pages()
+        }
+        MappingKind::Recursive => (TABLE_SIZE * TABLE_SIZE * TABLE_SIZE) as u64,
+    };
+
+    let mut start = ((i4 << 39) | (i3 << 30) | (i2 << 21) | (i1 << 12)) as VirtualAddress;
+    // Addresses in the higher half are sign extended
+    if i4 >= TABLE_SIZE / 2 {
+        start |= 0xffff_0000_0000_0000;
+    }
+
+    Some(Mapping {
+        start,
+        size: pages * PAGE_SIZE,
+        frame: frame.start_address(),
+        flags: entry.flags() - IGNORED_FLAGS - EntryFlags::HUGEPAGE,
+        kind,
+    })
+}
+
+pub fn print(mapper: &Mapper) {
+    for_each_mapping(mapper, |mapping| println!("{}", mapping));
+}
+
+// Prints the mappings of a table that isn't active
+pub fn print_inactive(
+    active_table: &mut ActivePageTable,
+    table: &mut InactivePageTable,
+    temporary_page: &mut TemporaryPage,
+) {
+    active_table.with(table, temporary_page, |mapper| print(mapper));
+}
+
+// Prints the entries on the way from the P4 table to `address`, up to the first one that doesn't
+// point to a table
+pub fn print_walk(mapper: &Mapper, address: VirtualAddress) {
+    let page = Page::containing_address(address);
+
+    let p4 = mapper.p4();
+    print_entry("P4", page.p4_index(), &p4[page.p4_index() as usize]);
+    let Some(p3) = p4.next_level(page.p4_index()) else {
+        return;
+    };
+    print_entry("P3", page.p3_index(), &p3[page.p3_index() as usize]);
+    let Some(p2) = p3.next_level(page.p3_index()) else {
+        return;
+    };
+    print_entry("P2", page.p2_index(), &p2[page.p2_index() as usize]);
+    let Some(p1) = p2.next_level(page.p2_index()) else {
+        return;
+    };
+    print_entry("P1", page.p1_index(), &p1[page.p1_index() as usize]);
+}
+
+fn print_entry(level: &str, index: u64, entry: &PageEntry) {
+    match entry.pointed_frame() {
+        Some(frame) => println!(
+            "{}[{}]: {:#x} {:?}",
+            level,
+            index,
+            frame.start_address(),
+            entry.flags()
+        ),
+        None if entry.is_unused() => println!("{}[{}]: unused", level, index),
+        None => println!("{}[{}]: not present {:?}", level, index, entry.flags()),
+    }
+}
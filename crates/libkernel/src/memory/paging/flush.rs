@@ -0,0 +1,109 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use x86_64::instructions::tlb::{self, InvPicdCommand};
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::VirtAddr;
+
+use crate::memory::VirtualAddress;
+
+use super::Page;
+
+// Flushing more pages than this one by one is slower than refilling the whole TLB
+pub const FLUSH_ALL_THRESHOLD: usize = 32;
+
+// Set if the CPU can flush every address space including global pages with one instruction
+static INVPCID: AtomicBool = AtomicBool::new(false);
+
+// Process-context identifiers stay disabled, every address space runs with PCID 0. INVPCID doesn't
+// need them for flushing everything.
+pub fn init() {
+    INVPCID.store(
+        core::arch::x86_64::__cpuid_count(7, 0).ebx & (1 << 10) != 0,
+        Ordering::Relaxed,
+    );
+}
+
+// Flushes a page of the active address space
+pub fn flush_page(address: VirtualAddress) {
+    tlb::flush(VirtAddr::new(address));
+}
+
+// Flushes every page of the active address space except for global ones
+pub fn flush_all() {
+    tlb::flush_all();
+}
+
+// Flushes every address space including global pages
+pub fn flush_everything() {
+    if INVPCID.load(Ordering::Relaxed) {
+        unsafe { tlb::flush_pcid(InvPicdCommand::All) };
+    } else {
+        // Toggling global pages invalidates the entries of all PCIDs
+        let flags = Cr4::read();
+        unsafe {
+            Cr4::write(flags ^ Cr4Flags::PAGE_GLOBAL);
+            Cr4::write(flags);
+        }
+    }
+}
+
+// Returned for every changed mapping. The CPU can keep using the old mapping until it is flushed from
+// the TLB, so it has to be consumed.
+#[must_use = "the TLB has to be flushed for the change to take effect"]
+pub struct MapperFlush(Page);
+
+impl MapperFlush {
+    pub(super) fn new(page: Page) -> Self {
+        MapperFlush(page)
+    }
+
+    pub fn flush(self) {
+        flush_page(self.0.start_address());
+    }
+
+    // Only for pages that can't be in the TLB, e.g. in an address space that isn't active
+    pub fn ignore(self) {}
+}
+
+// Collects the flushes of many changed mappings and flushes them at once. Everything is flushed once
+// there are more than FLUSH_ALL_THRESHOLD pages, global ones included because the batch doesn't know
+// which pages were global.
+#[must_use = "the TLB has to be flushed for the changes to take effect"]
+pub struct FlushBatch {
+    pages: [VirtualAddress; FLUSH_ALL_THRESHOLD],
+    len: usize,
+}
+
+impl FlushBatch {
+    pub fn new() -> Self {
+        FlushBatch {
+            pages: [0; FLUSH_ALL_THRESHOLD],
+            len: 0,
+        }
+    }
+
+    pub fn add(&mut self, flush: MapperFlush) {
+        if let Some(page) = self.pages.get_mut(self.len) {
+            *page = flush.0.start_address();
+        }
+        self.len += 1;
+    }
+
+    pub fn flush(self) {
+        if self.len > FLUSH_ALL_THRESHOLD {
+            flush_everything();
+        } else {
+            self.pages[..self.len]
+                .iter()
+                .for_each(|page| flush_page(*page));
+        }
+    }
+
+    pub fn ignore(self) {}
+}
+
+impl Default for FlushBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
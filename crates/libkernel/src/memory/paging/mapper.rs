@@ -1,20 +1,32 @@
 use core::ops::{Deref, DerefMut};
 
-use x86_64::{instructions::tlb, registers::control::Cr3};
+use x86_64::registers::control::Cr3;
 
+#[cfg(feature = "direct-map")]
+use crate::memory::direct_map;
 use crate::memory::{
-    frames::{FrameAlloc, PhysicalFrame},
+    frames::{FrameAlloc, PhysicalFrame, PAGE_SIZE},
     PhysicalAddress, VirtualAddress, TABLE_SIZE,
 };
 
 use super::{
-    entry::EntryFlags,
+    entry::{EntryFlags, PageEntry},
+    flush::{self, FlushBatch, MapperFlush},
     inactive::InactivePageTable,
+    supports_1gib_pages,
     tables::{PageTable, TableLevel4},
     temporary::TemporaryPage,
-    Page,
+    HugePageSize, Page, PageRange,
 };
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    AlreadyMapped(Page),
+    NotMapped(Page),
+    // No frame left for the mapped memory or a page table
+    OutOfFrames,
+}
+
 pub struct Mapper {
     p4: *mut PageTable<TableLevel4>,
 }
@@ -40,15 +52,24 @@ impl Mapper {
         frame: PhysicalFrame,
         flags: EntryFlags,
         allocator: &mut A,
-    ) {
-        let p4 = self.p4_mut();
+    ) -> Result<MapperFlush, MapError> {
+        if self.translate_page(page).is_some() {
+            return Err(MapError::AlreadyMapped(page));
+        }
 
-        let p3 = p4.next_level_create(page.p4_index(), allocator);
-        let p2 = p3.next_level_create(page.p3_index(), allocator);
-        let p1 = p2.next_level_create(page.p2_index(), allocator);
+        let Some(p1) = self
+            .p4_mut()
+            .next_level_create(page.p4_index(), allocator)
+            .and_then(|p3| p3.next_level_create(page.p3_index(), allocator))
+            .and_then(|p2| p2.next_level_create(page.p2_index(), allocator))
+        else {
+            // The tables created on the way are still empty
+            self.free_empty_tables(page, allocator);
+            return Err(MapError::OutOfFrames);
+        };
 
-        assert!(p1[page.p1_index() as usize].is_unused());
         p1[page.p1_index() as usize].set(frame, flags | EntryFlags::PRESENT);
+        Ok(MapperFlush::new(page))
     }
 
     pub fn identity_map<A: FrameAlloc>(
@@ -56,24 +77,440 @@ impl Mapper {
         frame: PhysicalFrame,
         flags: EntryFlags,
         allocator: &mut A,
-    ) {
+    ) -> Result<MapperFlush, MapError> {
         let page = Page::containing_address(frame.start_address());
         self.map_to(page, frame, flags, allocator)
     }
 
-    pub fn unmap<A: FrameAlloc>(&mut self, page: Page, allocator: &mut A) {
-        assert!(self.translate(page.start_address()).is_some());
+    // Maps every page of `range` to a new frame. Nothing is mapped if it fails.
+    pub fn map_range<A: FrameAlloc>(
+        &mut self,
+        range: PageRange,
+        flags: EntryFlags,
+        allocator: &mut A,
+    ) -> Result<FlushBatch, MapError> {
+        self.check_unmapped(range)?;
+
+        let mut batch = FlushBatch::new();
+        for page in range {
+            let result = match allocator.allocate_frame() {
+                Some(frame) => self
+                    .map_to(page, frame.clone(), flags, allocator)
+                    .inspect_err(|_| allocator.deallocate_frame(frame)),
+                None => Err(MapError::OutOfFrames),
+            };
+
+            match result {
+                Ok(flush) => batch.add(flush),
+                Err(error) => {
+                    batch.ignore();
+                    self.unmap_range(PageRange::new(range.start, page), allocator)
+                        .unwrap()
+                        .flush();
+                    return Err(error);
+                }
+            }
+        }
+        Ok(batch)
+    }
 
-        let p1 = self
+    // Maps `range` to the frames starting at `frame`. Huge pages are used wherever both are aligned
+    // to them. Nothing is mapped if it fails.
+    pub fn map_range_to<A: FrameAlloc>(
+        &mut self,
+        range: PageRange,
+        frame: PhysicalFrame,
+        flags: EntryFlags,
+        allocator: &mut A,
+    ) -> Result<FlushBatch, MapError> {
+        self.check_unmapped(range)?;
+
+        let mut batch = FlushBatch::new();
+        let mut mapped = 0;
+        while mapped < range.len() {
+            let page = Page {
+                number: range.start.number + mapped,
+            };
+            let frame = PhysicalFrame {
+                number: frame.number + mapped,
+            };
+
+            let size = [HugePageSize::Size1GiB, HugePageSize::Size2MiB]
+                .into_iter()
+                .filter(|size| *size != HugePageSize::Size1GiB || supports_1gib_pages())
+                .find(|size| {
+                    page.number % size.pages() == 0
+                        && frame.number % size.pages() == 0
+                        && range.len() - mapped >= size.pages()
+                });
+
+            let result = match size {
+                Some(size) => self
+                    .map_to_huge(page, frame, size, flags, allocator)
+                    .map(|flush| (flush, size.pages())),
+                None => self
+                    .map_to(page, frame, flags, allocator)
+                    .map(|flush| (flush, 1)),
+            };
+
+            match result {
+                Ok((flush, pages)) => {
+                    batch.add(flush);
+                    mapped += pages;
+                }
+                Err(error) => {
+                    batch.ignore();
+                    self.unmap_range_inner(PageRange::new(range.start, page), false, allocator)
+                        .unwrap()
+                        .flush();
+                    return Err(error);
+                }
+            }
+        }
+        Ok(batch)
+    }
+
+    // Maps the frames from `start` up to, but not including, `end` to the pages with the same address
+    pub fn identity_map_range<A: FrameAlloc>(
+        &mut self,
+        start: PhysicalFrame,
+        end: PhysicalFrame,
+        flags: EntryFlags,
+        allocator: &mut A,
+    ) -> Result<FlushBatch, MapError> {
+        let range = PageRange::new(
+            Page::containing_address(start.start_address()),
+            Page::containing_address(end.start_address()),
+        );
+        self.map_range_to(range, start, flags, allocator)
+    }
+
+    pub fn map_to_huge<A: FrameAlloc>(
+        &mut self,
+        page: Page,
+        frame: PhysicalFrame,
+        size: HugePageSize,
+        flags: EntryFlags,
+        allocator: &mut A,
+    ) -> Result<MapperFlush, MapError> {
+        assert!(
+            page.number % size.pages() == 0 && frame.number % size.pages() == 0,
+            "Huge pages have to be aligned to their size"
+        );
+
+        let p3 = self
             .p4_mut()
+            .next_level_create(page.p4_index(), allocator)
+            .ok_or(MapError::OutOfFrames)?;
+        let entry = match size {
+            HugePageSize::Size1GiB => {
+                assert!(supports_1gib_pages(), "1 GiB pages are not supported");
+                &mut p3[page.p3_index() as usize]
+            }
+            HugePageSize::Size2MiB => {
+                // Already part of a 1 GiB page
+                if p3[page.p3_index() as usize]
+                    .flags()
+                    .contains(EntryFlags::HUGEPAGE)
+                {
+                    return Err(MapError::AlreadyMapped(page));
+                }
+                let Some(p2) = p3.next_level_create(page.p3_index(), allocator) else {
+                    // The P3 table can be a new one that is still empty
+                    self.free_empty_tables(page, allocator);
+                    return Err(MapError::OutOfFrames);
+                };
+                &mut p2[page.p2_index() as usize]
+            }
+        };
+
+        if !entry.is_unused() {
+            return Err(MapError::AlreadyMapped(page));
+        }
+        entry.set(frame, flags | EntryFlags::PRESENT | EntryFlags::HUGEPAGE);
+        Ok(MapperFlush::new(page))
+    }
+
+    // Returns the first frame of the huge page. The frames aren't freed, huge pages usually map memory
+    // that didn't come from the frame allocator.
+    pub fn unmap_huge<A: FrameAlloc>(
+        &mut self,
+        page: Page,
+        size: HugePageSize,
+        allocator: &mut A,
+    ) -> Result<(PhysicalFrame, MapperFlush), MapError> {
+        assert!(
+            page.number % size.pages() == 0,
+            "Huge pages have to be aligned to their size"
+        );
+
+        let entry = self
+            .huge_entry_mut(page, size)
+            .ok_or(MapError::NotMapped(page))?;
+        let frame = entry.pointed_frame().unwrap();
+        entry.set_unused();
+
+        self.free_empty_tables(page, allocator);
+        Ok((frame, MapperFlush::new(page)))
+    }
+
+    // Size of the huge page `page` is part of, None if it is mapped by a P1 table or not at all
+    pub fn huge_page_size(&mut self, page: Page) -> Option<HugePageSize> {
+        [HugePageSize::Size1GiB, HugePageSize::Size2MiB]
+            .into_iter()
+            .find(|size| self.huge_entry_mut(page, *size).is_some())
+    }
+
+    // Replaces the huge page containing `page` by a table of the next smaller pages with the same
+    // flags, so a 1 GiB page becomes 2 MiB pages. Returns false if `page` isn't part of a huge page.
+    // The huge page is unusable while the new table is filled, so it mustn't contain the code or the
+    // stack doing the split.
+    pub fn split_huge<A: FrameAlloc>(
+        &mut self,
+        page: Page,
+        allocator: &mut A,
+    ) -> Result<bool, MapError> {
+        let Some(size) = self.huge_page_size(page) else {
+            return Ok(false);
+        };
+
+        let table_frame = allocator.allocate_frame().ok_or(MapError::OutOfFrames)?;
+        let entry = self.huge_entry_mut(page, size).unwrap();
+        let start = entry.pointed_frame().unwrap();
+        let flags = entry.flags();
+
+        // Access is the most restrictive of all levels, so the huge page flags move to the new entries
+        entry.set(
+            table_frame,
+            EntryFlags::PRESENT | EntryFlags::WRITABLE | (flags & EntryFlags::USERACCESSIBLE),
+        );
+
+        // The address of the new table was part of the huge page through the recursive mapping
+        let p3 = self.p4_mut().next_level_mut(page.p4_index()).unwrap();
+        match size {
+            HugePageSize::Size1GiB => {
+                let p2 = p3.next_level_mut(page.p3_index()).unwrap();
+                flush::flush_page(p2 as *const _ as VirtualAddress);
+                for i in 0..TABLE_SIZE {
+                    p2[i].set(
+                        PhysicalFrame {
+                            number: start.number + (i * TABLE_SIZE) as u64,
+                        },
+                        flags,
+                    );
+                }
+            }
+            HugePageSize::Size2MiB => {
+                let p1 = p3
+                    .next_level_mut(page.p3_index())
+                    .and_then(|p2| p2.next_level_mut(page.p2_index()))
+                    .unwrap();
+                flush::flush_page(p1 as *const _ as VirtualAddress);
+                for i in 0..TABLE_SIZE {
+                    p1[i].set(
+                        PhysicalFrame {
+                            number: start.number + i as u64,
+                        },
+                        flags - EntryFlags::HUGEPAGE,
+                    );
+                }
+            }
+        }
+
+        // The TLB mustn't keep the huge page and the smaller ones at the same time
+        flush::flush_page(page.start_address());
+        Ok(true)
+    }
+
+    fn huge_entry_mut(&mut self, page: Page, size: HugePageSize) -> Option<&mut PageEntry> {
+        let p3 = self.p4_mut().next_level_mut(page.p4_index())?;
+        let entry = match size {
+            HugePageSize::Size1GiB => &mut p3[page.p3_index() as usize],
+            HugePageSize::Size2MiB => {
+                &mut p3.next_level_mut(page.p3_index())?[page.p2_index() as usize]
+            }
+        };
+
+        entry
+            .flags()
+            .contains(EntryFlags::PRESENT | EntryFlags::HUGEPAGE)
+            .then_some(entry)
+    }
+
+    fn p1_entry_mut(&mut self, page: Page) -> Option<&mut PageEntry> {
+        self.p4_mut()
             .next_level_mut(page.p4_index())
             .and_then(|p3| p3.next_level_mut(page.p3_index()))
             .and_then(|p2| p2.next_level_mut(page.p2_index()))
-            .expect("mapping code does not support huge pages");
-        let frame = p1[page.p1_index() as usize].pointed_frame().unwrap();
-        p1[page.p1_index() as usize].set_unused();
-        // TODO free p(1,2,3) table if empty
-        allocator.deallocate_frame(frame);
+            .map(|p1| &mut p1[page.p1_index() as usize])
+    }
+
+    // A huge page containing `page` is split into 4 KiB pages first. The page is flushed before its
+    // frame is freed, so nothing can reach the frame through the TLB once it is handed out again.
+    // Frames of pages split out of a huge page aren't freed, like with `unmap_huge`.
+    pub fn unmap<A: FrameAlloc>(&mut self, page: Page, allocator: &mut A) -> Result<(), MapError> {
+        let split = self.huge_page_size(page).is_some();
+        let (frame, flush) = self.unmap_keep_frame(page, allocator)?;
+        flush.flush();
+        if !split {
+            allocator.deallocate_frame(frame);
+        }
+        Ok(())
+    }
+
+    // Like `unmap`, but returns the frame instead of freeing it. Tables that became empty are still
+    // freed.
+    pub fn unmap_keep_frame<A: FrameAlloc>(
+        &mut self,
+        page: Page,
+        allocator: &mut A,
+    ) -> Result<(PhysicalFrame, MapperFlush), MapError> {
+        if self.translate_page(page).is_none() {
+            return Err(MapError::NotMapped(page));
+        }
+        while self.split_huge(page, allocator)? {}
+
+        let entry = self.p1_entry_mut(page).unwrap();
+        let frame = entry.pointed_frame().unwrap();
+        entry.set_unused();
+
+        self.free_empty_tables(page, allocator);
+        Ok((frame, MapperFlush::new(page)))
+    }
+
+    // Unmaps every page in `range` and frees the frames of the 4 KiB pages like `unmap`, those pages
+    // are flushed right away. Huge pages that lie in it completely are unmapped like with
+    // `unmap_huge`, without freeing their frames. Nothing is unmapped if a page isn't mapped.
+    pub fn unmap_range<A: FrameAlloc>(
+        &mut self,
+        range: PageRange,
+        allocator: &mut A,
+    ) -> Result<FlushBatch, MapError> {
+        self.unmap_range_inner(range, true, allocator)
+    }
+
+    fn unmap_range_inner<A: FrameAlloc>(
+        &mut self,
+        range: PageRange,
+        free_frames: bool,
+        allocator: &mut A,
+    ) -> Result<FlushBatch, MapError> {
+        self.check_mapped(range)?;
+
+        let mut batch = FlushBatch::new();
+        let mut page = range.start;
+        while range.contains(page) {
+            let result = match self.whole_huge_page(page, range) {
+                Some(size) => self
+                    .unmap_huge(page, size, allocator)
+                    .map(|(_, flush)| (Some(flush), size.pages())),
+                None if free_frames => self.unmap(page, allocator).map(|_| (None, 1)),
+                None => self
+                    .unmap_keep_frame(page, allocator)
+                    .map(|(_, flush)| (Some(flush), 1)),
+            };
+
+            // Splitting can run out of frames, the pages unmapped so far stay unmapped
+            match result {
+                Ok((flush, pages)) => {
+                    if let Some(flush) = flush {
+                        batch.add(flush);
+                    }
+                    page.number += pages;
+                }
+                Err(error) => {
+                    batch.flush();
+                    return Err(error);
+                }
+            }
+        }
+        Ok(batch)
+    }
+
+    // Size of the huge page starting at `page` if it lies in `range` completely
+    fn whole_huge_page(&mut self, page: Page, range: PageRange) -> Option<HugePageSize> {
+        self.huge_page_size(page).filter(|size| {
+            page.number % size.pages() == 0 && range.end.number - page.number >= size.pages()
+        })
+    }
+
+    fn check_mapped(&self, range: PageRange) -> Result<(), MapError> {
+        match self.find_page(range, true) {
+            Some(page) => Err(MapError::NotMapped(page)),
+            None => Ok(()),
+        }
+    }
+
+    fn check_unmapped(&self, range: PageRange) -> Result<(), MapError> {
+        match self.find_page(range, false) {
+            Some(page) => Err(MapError::AlreadyMapped(page)),
+            None => Ok(()),
+        }
+    }
+
+    // First page in `range` that is mapped if `mapped` is false or unmapped if it is true. Unused
+    // entries and huge pages are skipped as a whole instead of page by page.
+    fn find_page(&self, range: PageRange, mapped: bool) -> Option<Page> {
+        let mut page = range.start;
+        while range.contains(page) {
+            let (is_mapped, pages) = self.mapping_span(page);
+            if is_mapped != mapped {
+                return Some(page);
+            }
+            page.number = (page.number / pages + 1) * pages;
+        }
+        None
+    }
+
+    // Whether `page` is mapped and the amount of pages mapped or left unmapped by the same entry
+    fn mapping_span(&self, page: Page) -> (bool, u64) {
+        const P3_PAGES: u64 = (TABLE_SIZE * TABLE_SIZE * TABLE_SIZE) as u64;
+        const P2_PAGES: u64 = (TABLE_SIZE * TABLE_SIZE) as u64;
+        const P1_PAGES: u64 = TABLE_SIZE as u64;
+
+        let Some(p3) = self.p4().next_level(page.p4_index()) else {
+            return (false, P3_PAGES);
+        };
+        if p3[page.p3_index() as usize]
+            .flags()
+            .contains(EntryFlags::PRESENT | EntryFlags::HUGEPAGE)
+        {
+            return (true, P2_PAGES);
+        }
+        let Some(p2) = p3.next_level(page.p3_index()) else {
+            return (false, P2_PAGES);
+        };
+        if p2[page.p2_index() as usize]
+            .flags()
+            .contains(EntryFlags::PRESENT | EntryFlags::HUGEPAGE)
+        {
+            return (true, P1_PAGES);
+        }
+        let Some(p1) = p2.next_level(page.p2_index()) else {
+            return (false, P1_PAGES);
+        };
+        (p1[page.p1_index() as usize].pointed_frame().is_some(), 1)
+    }
+
+    // Frees the P1 and P2 tables on the way to `page` that have no used entries left, and the P3
+    // table as well in the lower half
+    fn free_empty_tables<A: FrameAlloc>(&mut self, page: Page, allocator: &mut A) {
+        // The last P4 entry maps the tables themselves
+        if page.p4_index() == TABLE_SIZE as u64 - 1 {
+            return;
+        }
+
+        let p4 = self.p4_mut();
+        if let Some(p3) = p4.next_level_mut(page.p4_index()) {
+            if let Some(p2) = p3.next_level_mut(page.p3_index()) {
+                p2.free_next_level_if_empty(page.p2_index(), page.table_address(1), allocator);
+            }
+            p3.free_next_level_if_empty(page.p3_index(), page.table_address(2), allocator);
+        }
+        // Every address space shares the P3 tables of the higher half, so its P4 entries stay
+        if page.p4_index() < TABLE_SIZE as u64 / 2 {
+            p4.free_next_level_if_empty(page.p4_index(), page.table_address(3), allocator);
+        }
     }
 
     pub fn translate_page(&self, page: Page) -> Option<PhysicalFrame> {
@@ -117,9 +554,9 @@ impl Mapper {
     }
 
     pub fn translate(&self, virtual_address: VirtualAddress) -> Option<PhysicalAddress> {
-        let offset = virtual_address % TABLE_SIZE as u64;
+        let offset = virtual_address % PAGE_SIZE;
         self.translate_page(Page::containing_address(virtual_address))
-            .map(|frame| frame.number * TABLE_SIZE as u64 + offset)
+            .map(|frame| frame.start_address() + offset)
     }
 }
 
@@ -127,6 +564,9 @@ pub struct ActivePageTable {
     mapper: Mapper,
 }
 
+// There is only one active table and it is only reached through the recursive mapping
+unsafe impl Send for ActivePageTable {}
+
 impl ActivePageTable {
     pub unsafe fn new() -> Self {
         ActivePageTable {
@@ -142,6 +582,13 @@ impl ActivePageTable {
     ) where
         F: FnOnce(&mut Mapper),
     {
+        // The inactive table can be edited in place, the recursive mapping and the TLB stay as they are
+        #[cfg(feature = "direct-map")]
+        if let Some(p4) = direct_map::phys_to_virt(table.p4_frame.start_address()) {
+            f(&mut Mapper { p4: p4 as *mut _ });
+            return;
+        }
+
         {
             let backup = Cr3::read();
             let backup = PhysicalFrame::by_addr(backup.0.start_address().as_u64());
@@ -154,12 +601,13 @@ impl ActivePageTable {
                 },
                 EntryFlags::PRESENT | EntryFlags::WRITABLE,
             );
-            tlb::flush_all();
+            // Every recursively mapped table changes, only a full flush catches all of them
+            flush::flush_all();
 
             f(self);
 
             p4_table[511].set(backup, EntryFlags::PRESENT | EntryFlags::WRITABLE);
-            tlb::flush_all();
+            flush::flush_all();
         }
         temporary_page.unmap(self)
     }
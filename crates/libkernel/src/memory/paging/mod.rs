@@ -2,13 +2,38 @@ use crate::memory::TABLE_SIZE;
 
 use super::{frames::PAGE_SIZE, PhysicalAddress, VirtualAddress};
 
+pub mod dump;
 pub mod entry;
+pub mod flush;
 pub mod inactive;
 pub mod mapper;
 pub mod tables;
 pub mod temporary;
 
-#[derive(Debug, Clone, Copy)]
+// Huge pages are mapped directly by a P2 (2 MiB) or P3 (1 GiB) entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HugePageSize {
+    Size2MiB,
+    Size1GiB,
+}
+
+impl HugePageSize {
+    // Amount of 4 KiB pages
+    pub fn pages(&self) -> u64 {
+        match self {
+            HugePageSize::Size2MiB => TABLE_SIZE as u64,
+            HugePageSize::Size1GiB => TABLE_SIZE as u64 * TABLE_SIZE as u64,
+        }
+    }
+}
+
+// 1 GiB pages are an optional CPU feature
+pub fn supports_1gib_pages() -> bool {
+    let cpuid = core::arch::x86_64::__cpuid(0x8000_0001);
+    cpuid.edx & (1 << 26) != 0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Page {
     pub(crate) number: u64,
 }
@@ -41,4 +66,61 @@ impl Page {
     pub(crate) fn p1_index(&self) -> u64 {
         (self.number >> 0) & 0o777
     }
+
+    // Address of the P1 (level 1), P2 or P3 table on the way to this page through the recursive P4
+    // entry
+    pub(crate) fn table_address(&self, level: u32) -> VirtualAddress {
+        let number = self.number & 0o777_777_777_777;
+        (!0 << (48 - 9 * level)) | (number >> (9 * level) << 12)
+    }
+}
+
+// Pages from `start` up to, but not including, `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageRange {
+    pub start: Page,
+    pub end: Page,
+}
+
+impl PageRange {
+    pub fn new(start: Page, end: Page) -> Self {
+        assert!(
+            start.number <= end.number,
+            "Page range ends before it starts"
+        );
+        PageRange { start, end }
+    }
+
+    // Every page with a byte in `start..end`
+    pub fn containing(start: VirtualAddress, end: VirtualAddress) -> Self {
+        PageRange::new(
+            Page::containing_address(start),
+            Page::containing_address(end.div_ceil(PAGE_SIZE) * PAGE_SIZE),
+        )
+    }
+
+    pub fn len(&self) -> u64 {
+        self.end.number - self.start.number
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, page: Page) -> bool {
+        (self.start.number..self.end.number).contains(&page.number)
+    }
+}
+
+impl Iterator for PageRange {
+    type Item = Page;
+
+    fn next(&mut self) -> Option<Page> {
+        if self.is_empty() {
+            return None;
+        }
+        let page = self.start;
+        self.start.number += 1;
+        Some(page)
+    }
 }
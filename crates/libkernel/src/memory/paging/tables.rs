@@ -3,11 +3,14 @@ use core::{
     ops::{Index, IndexMut},
 };
 
+#[cfg(feature = "direct-map")]
+use crate::memory::direct_map;
 use crate::memory::frames::FrameAlloc;
+use crate::memory::VirtualAddress;
 
 use super::{
     entry::{EntryFlags, PageEntry},
-    TABLE_SIZE,
+    flush, TABLE_SIZE,
 };
 
 pub trait TableLevel {}
@@ -47,6 +50,10 @@ impl<T: TableLevel> PageTable<T> {
         }
         self
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.iter().all(|entry| entry.is_unused())
+    }
 }
 
 impl<T: TableLevel> Index<usize> for PageTable<T> {
@@ -66,7 +73,15 @@ impl<T: TableLevel> IndexMut<usize> for PageTable<T> {
 impl<H: PageHiearchy> PageTable<H> {
     fn next_level_addr(&self, index: u64) -> Option<u64> {
         let flags = self[index as usize].flags();
-        if flags.contains(EntryFlags::PRESENT) {
+        // A huge page maps memory instead of a table
+        if flags.contains(EntryFlags::PRESENT) && !flags.contains(EntryFlags::HUGEPAGE) {
+            #[cfg(feature = "direct-map")]
+            if let Some(address) =
+                direct_map::phys_to_virt(self[index as usize].pointed_frame()?.start_address())
+            {
+                return Some(address);
+            }
+
             let table_address = self as *const _ as u64;
             Some((table_address << 9) | (index << 12))
         } else {
@@ -84,24 +99,46 @@ impl<H: PageHiearchy> PageTable<H> {
             .map(|addr| unsafe { &mut *(addr as *mut _) })
     }
 
+    // Returns None if there is no frame left for a new table
     pub fn next_level_create<A: FrameAlloc>(
         &mut self,
         index: u64,
         allocator: &mut A,
-    ) -> &mut PageTable<H::Target> {
+    ) -> Option<&mut PageTable<H::Target>> {
         match self.next_level(index) {
-            Some(_) => self.next_level_mut(index).unwrap(),
+            Some(_) => self.next_level_mut(index),
             None => {
                 assert!(
                     !self.entries[index as usize]
                         .flags()
                         .contains(EntryFlags::HUGEPAGE),
-                    "Cannot map to hugepages"
+                    "Cannot map to hugepages, split them first"
                 );
-                let frame = allocator.allocate_frame().expect("Out of memory");
+                let frame = allocator.allocate_frame()?;
                 self.entries[index as usize].set(frame, EntryFlags::PRESENT | EntryFlags::WRITABLE);
-                self.next_level_mut(index).unwrap().zero()
+                Some(self.next_level_mut(index).unwrap().zero())
             }
         }
     }
+
+    // Clears entry `index` and frees the table it points to if that table has no used entries left.
+    // `recursive_address` is where the table is mapped through the recursive P4 entry, which isn't
+    // where it was accessed if that happened through the direct map.
+    pub fn free_next_level_if_empty<A: FrameAlloc>(
+        &mut self,
+        index: u64,
+        recursive_address: VirtualAddress,
+        allocator: &mut A,
+    ) -> bool {
+        if !self.next_level(index).is_some_and(|table| table.is_empty()) {
+            return false;
+        }
+
+        let frame = self[index as usize].pointed_frame().unwrap();
+        self[index as usize].set_unused();
+        // The table stays reachable through a stale TLB entry of its recursive mapping otherwise
+        flush::flush_page(recursive_address);
+        allocator.deallocate_frame(frame);
+        true
+    }
 }
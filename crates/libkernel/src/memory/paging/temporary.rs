@@ -33,12 +33,19 @@ impl TemporaryPage {
             "temporary page is already mapped"
         );
 
-        active_table.map_to(self.page, frame, EntryFlags::WRITABLE, &mut self.allocator);
+        active_table
+            .map_to(self.page, frame, EntryFlags::WRITABLE, &mut self.allocator)
+            .expect("Cannot map the temporary page")
+            .flush();
         self.page.start_address()
     }
 
+    // The mapped frame isn't freed, it belongs to whoever mapped it
     pub fn unmap(&mut self, active_table: &mut ActivePageTable) {
-        active_table.unmap(self.page, &mut self.allocator)
+        let (_, flush) = active_table
+            .unmap_keep_frame(self.page, &mut self.allocator)
+            .expect("Temporary page is not mapped");
+        flush.flush();
     }
 
     pub fn map_table_frame(
@@ -0,0 +1,70 @@
+use core::fmt;
+
+use crate::memory::frames::PAGE_SIZE;
+use crate::memory::heap::{HEAP_MAX_SIZE, HEAP_START};
+use crate::memory::paging::Page;
+use crate::memory::{VirtualAddress, TEMPORARY_PAGE_ADDRESS};
+use crate::BOOT_INFO;
+
+// First address reached through the last P4 entry, which maps the page tables themselves
+const PAGE_TABLES_START: VirtualAddress = 0xffff_ff80_0000_0000;
+
+// Formats the name of the kernel region `address` is part of, e.g. for page faults. Doesn't allocate
+// or lock anything.
+pub struct Region(pub VirtualAddress);
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        unsafe extern "C" {
+            static p4_table: u8;
+            static stack_start: u8;
+            static stack_end: u8;
+        }
+
+        let address = self.0;
+        let stack = core::ptr::addr_of!(stack_start) as VirtualAddress
+            ..core::ptr::addr_of!(stack_end) as VirtualAddress;
+        // The boot page tables below the stack are unmapped once the kernel is remapped
+        let guard = core::ptr::addr_of!(p4_table) as VirtualAddress..stack.start;
+        let temporary_page = Page::containing_address(TEMPORARY_PAGE_ADDRESS).start_address();
+
+        if (HEAP_START..HEAP_START + HEAP_MAX_SIZE).contains(&address) {
+            return f.write_str("heap");
+        }
+        if (temporary_page..temporary_page + PAGE_SIZE).contains(&address) {
+            return f.write_str("temporary page");
+        }
+        if stack.contains(&address) {
+            return f.write_str("boot stack");
+        }
+        if guard.contains(&address) {
+            return f.write_str("boot stack guard page");
+        }
+        if address >= PAGE_TABLES_START {
+            return f.write_str("page tables");
+        }
+        #[cfg(feature = "direct-map")]
+        if (crate::memory::direct_map::PHYSICAL_MEMORY_OFFSET..HEAP_START).contains(&address) {
+            return f.write_str("direct map");
+        }
+
+        // The kernel is identity mapped, so section addresses are virtual addresses too
+        let section = BOOT_INFO
+            .get()
+            .and_then(|boot_info| boot_info.elf_sections())
+            .and_then(|mut sections| {
+                sections.find(|section| {
+                    section.is_allocated()
+                        && (section.start_address()..section.end_address()).contains(&address)
+                })
+            });
+        match section {
+            Some(section) => write!(
+                f,
+                "ELF section {}",
+                section.name().unwrap_or("<Invalid section name>")
+            ),
+            None => f.write_str("unknown"),
+        }
+    }
+}
@@ -21,11 +21,11 @@ pub(crate) fn _print(args: core::fmt::Arguments) {
 
 #[macro_export]
 macro_rules! serial_println {
-    () => (print!("\n"));
-    ($($arg:tt)*) => (crate::print!("{}\n", format_args!($($arg)*)));
+    () => ($crate::serial_print!("\n"));
+    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
 }
 
 #[macro_export]
 macro_rules! serial_print {
-    ($($arg:tt)*) => ($crate::vga::text::_print(format_args!($($arg)*)));
+    ($($arg:tt)*) => ($crate::serial::_print(format_args!($($arg)*)));
 }